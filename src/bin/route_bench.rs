@@ -0,0 +1,91 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Compare the old linear regex scan with the routes tree of the dispatcher,
+//! both only look for the routes matching a path
+
+extern crate regex;
+extern crate time;
+extern crate web_dispatcher;
+
+use regex::Regex;
+use web_dispatcher::{Dispatcher, Get, Request, Response};
+
+static ROUTES_COUNT: uint = 500;
+static LOOKUPS_COUNT: uint = 10000;
+
+fn bench_route(_: &Request, _: ()) -> Box<Response> {
+    box () () as Box<Response>
+}
+
+// the matching regex built by the old dispatcher for each route
+fn create_match_regex(re_var: &Regex, r: &str) -> Regex {
+    let mut match_reg: String = String::from_char(1, '^');
+    match_reg = match_reg.append(re_var.replace_all(r, "([0-9a-zA-Z-_]+)").as_slice());
+    match_reg = match_reg.replace("*", "[0-9a-zA-Z-_]*");
+    match_reg.push_str("/??$");
+    Regex::new(match_reg.as_slice()).unwrap()
+}
+
+fn bench_linear(routes: &Vec<String>, paths: &Vec<String>) -> u64 {
+    let re_var = Regex::new(":[0-9a-zA-Z-_]+").unwrap();
+    let regexes: Vec<Regex> = routes.iter()
+        .map(|r| create_match_regex(&re_var, r.as_slice()))
+        .collect();
+
+    let start = time::precise_time_ns();
+    for i in range(0u, LOOKUPS_COUNT) {
+        let path = paths[i % paths.len()].as_slice();
+        assert!(regexes.iter().any(|re| re.is_match(path)));
+    }
+    time::precise_time_ns() - start
+}
+
+fn bench_tree(routes: &Vec<String>, paths: &Vec<String>) -> u64 {
+    let mut dispatcher = Dispatcher::<()>::new(&[]);
+    for r in routes.iter() {
+        dispatcher.add(bench_route, r.as_slice(), Get);
+    }
+
+    let start = time::precise_time_ns();
+    for i in range(0u, LOOKUPS_COUNT) {
+        let path = paths[i % paths.len()].as_slice();
+        // a tree lookup, without running the route
+        assert!(!dispatcher.allowed_methods(path).is_empty());
+    }
+    time::precise_time_ns() - start
+}
+
+fn main() {
+    let mut routes = Vec::new();
+    let mut paths = Vec::new();
+    for i in range(0u, ROUTES_COUNT) {
+        routes.push(format!("/resource{}/:id/child/*/edit", i));
+        paths.push(format!("/resource{}/42/child/anything/edit", i));
+    }
+
+    let linear = bench_linear(&routes, &paths);
+    let tree = bench_tree(&routes, &paths);
+    println!("{} routes, {} lookups", ROUTES_COUNT, LOOKUPS_COUNT);
+    println!("linear regex scan: {} ms", linear / 1000000);
+    println!("routes tree:       {} ms", tree / 1000000);
+}
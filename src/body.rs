@@ -0,0 +1,134 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Parsing of the body of the requests
+
+use std::ascii::StrAsciiExt;
+
+use request::Headers;
+
+/// A part of a `multipart/form-data` body
+#[deriving(Clone, Show, PartialEq)]
+pub struct Part {
+    /// the headers of the part
+    pub headers: Headers,
+    /// the name of the form field
+    pub name: Option<String>,
+    /// the name of the uploaded file, None for a simple field
+    pub filename: Option<String>,
+    /// the content of the part
+    pub data: Vec<u8>
+}
+
+/// The media type of a `Content-Type` header, in lower case and without its
+/// parameters, e.g `text/html` for `text/html; charset=utf-8`
+pub fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_string().into_ascii_lower()
+}
+
+/// A parameter of a header value, e.g the `boundary` of a `Content-Type`,
+/// the quotes around the value are removed
+pub fn header_param(value: &str, name: &str) -> Option<String> {
+    for param in value.split(';').skip(1) {
+        let param = param.trim();
+        match param.find('=') {
+            Some(i) if param.slice_to(i).trim().eq_ignore_ascii_case(name) => {
+                let v = param.slice_from(i + 1).trim();
+                let v = if v.len() >= 2 && v.starts_with("\"") && v.ends_with("\"") {
+                    v.slice(1, v.len() - 1)
+                } else {
+                    v
+                };
+                return Some(v.to_string());
+            },
+            _ => {}
+        }
+    }
+    None
+}
+
+// find the position of `needle` in `haystack`, starting at `from`
+fn find_bytes(haystack: &[u8], needle: &[u8], from: uint) -> Option<uint> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    range(from, haystack.len() - needle.len() + 1)
+        .find(|&i| haystack.slice(i, i + needle.len()) == needle)
+}
+
+/// Parse a `multipart/form-data` body, return None if the body is malformed
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Option<Vec<Part>> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut parts = Vec::new();
+
+    let mut pos = match find_bytes(body, delimiter, 0) {
+        Some(p) => p + delimiter.len(),
+        None    => return None
+    };
+    loop {
+        // the last delimiter is followed by `--`
+        if body.slice_from(pos).starts_with(b"--") {
+            return Some(parts);
+        }
+        if !body.slice_from(pos).starts_with(b"\r\n") {
+            return None;
+        }
+        let start = pos + 2;
+        let end = match find_bytes(body, format!("\r\n--{}", boundary).as_bytes(), start) {
+            Some(e) => e,
+            None    => return None
+        };
+        match parse_part(body.slice(start, end)) {
+            Some(p) => parts.push(p),
+            None    => return None
+        }
+        pos = end + 2 + delimiter.len();
+    }
+}
+
+// parse a part of a multipart body, its headers then its content
+fn parse_part(part: &[u8]) -> Option<Part> {
+    let headers_end = match find_bytes(part, b"\r\n\r\n", 0) {
+        Some(e) => e,
+        None    => return None
+    };
+    let mut headers = Headers::new();
+    let raw_headers = String::from_utf8_lossy(part.slice_to(headers_end)).into_string();
+    for line in raw_headers.as_slice().split_str("\r\n").filter(|l| !l.is_empty()) {
+        match line.find(':') {
+            Some(i) => headers.add(line.slice_to(i).trim(), line.slice_from(i + 1).trim()),
+            None    => return None
+        }
+    }
+
+    let (name, filename) = match headers.get("Content-Disposition") {
+        Some(d) => (header_param(d, "name"), header_param(d, "filename")),
+        None    => (None, None)
+    };
+    Some(Part {
+        headers: headers,
+        name: name,
+        filename: filename,
+        data: part.slice_from(headers_end + 4).to_vec()
+    })
+}
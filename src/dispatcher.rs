@@ -21,6 +21,45 @@
 // SOFTWARE.
 
 //! The web dispatcher
+//!
+//! Routes are looked up in a segment tree. When several routes match a path
+//! the dispatcher runs the most specific one, see the `router` module for the
+//! precedence rules. Routes made only of static segments are found with a
+//! single hash lookup.
+//!
+//! Routes sharing a prefix can be added together in a group, with hooks run
+//! before their functions and their own producer, see `Dispatcher::group`.
+//!
+//! Another dispatcher can be mounted under a prefix, see `Dispatcher::mount`.
+//!
+//! Middlewares run around the routes, for all the routes of the dispatcher
+//! or for a single route, see the `middleware` module.
+//!
+//! The function of a route wrapped in `Isolated` runs in its own task, its
+//! failure is returned as a `HandlerFailed` error. The middlewares and the
+//! hooks of the route are not isolated.
+//!
+//! Dispatching only reads the dispatcher, so a dispatcher can be shared
+//! between several tasks with an `Arc`. For this the handlers, the
+//! middlewares, the error handlers, the producers and the mounted
+//! dispatchers are required to be `Send + Sync`, including for a dispatcher
+//! used by a single task: a closure or a handler keeping its state in a
+//! `Rc` or a `Cell` is not accepted anymore, its state goes in an `Arc`
+//! with a `Mutex` or an atomic instead.
+//!
+//! Unless disabled, a HEAD request without a HEAD route is dispatched to the
+//! GET route of the path, without the body of its response, and an OPTIONS
+//! request without an OPTIONS route is answered by the dispatcher with the
+//! methods allowed for the path in the `Allow` header.
+//!
+//! The dispatcher accepts full request targets: the query string and the
+//! fragment are ignored by the routing, the query string of the dispatched
+//! target gives the query params of the request.
+//!
+//! The path is normalised before its matching, see `url::normalize_path`:
+//! the routes see the decoded values in their params while the raw path is
+//! kept in the uri of the request. The paths ending with a slash are
+//! dispatched following the `TrailingSlash` policy of the dispatcher.
 
 #![allow(visible_private_types)]
 
@@ -28,54 +67,148 @@ use std::default::Default;
 use std::collections::HashMap;
 use std::fmt::{Show, Formatter, FormatError};
 
-use regex::Regex;
+use error::{DispatchError, NotFound, MethodNotAllowed, HandlerFailed, allow_header};
+use error::{UnknownRoute, MissingParameter, InvalidParameter, InvalidPath};
+use method::{Method, Get, Head, Options};
+use response::{Response, Request, HttpResponse};
+use response::{Resp, Filled, NoResp, InternalError, RoutingError};
+use router::{Node, Segment, Specificity, default_constraints, expand_route};
+use router::{parse_route, split_path, Static, Var, Wildcard, CatchAll};
+use tools::{RoutesFnType, Handler, HookFnType, UnusedProducer, Producer, ErrorHandler};
+use middleware::{Middleware, RouteInfo, run_chain};
+use request::WebRequest;
+use url::{percent_encode, split_target, normalize_path, parse_query};
 
-use method::Method;
-use response::{Response, Request};
-use tools::{RoutesFnType, UnusedProducer, Producer};
+// one of the forms of a route, a route with optional parts has several forms
+struct RouteVariant {
+    var_names: Vec<String>,
+    specificity: Specificity
+}
 
+pub struct RouteDatas<U> {
+    variants: Vec<RouteVariant>,
+    defaults: HashMap<String, String>,
+    index: uint,
+    hooks: Vec<HookFnType>,
+    middlewares: Vec<Box<Middleware<U> + Send + Sync>>,
+    producer: Option<uint>,
+    f: Box<Handler<U> + Send + Sync>
+}
 
-static RE_VAR: Regex = regex!(":[0-9a-zA-Z-_]+");
+impl<U> RouteDatas<U> {
+    // check if the route can be found with a simple hash lookup
+    fn is_static(&self) -> bool {
+        self.variants.len() == 1 && self.variants[0].specificity.is_static()
+    }
+}
 
-pub struct RouteDatas<U> {
-    var_names: Vec<String>,
-    regex: Regex,
-    f: RoutesFnType<U>
+// the error handlers of the dispatcher or of a group
+struct ErrorHandlers {
+    not_found: Option<Box<ErrorHandler + Send + Sync>>,
+    error: Option<Box<ErrorHandler + Send + Sync>>
+}
+
+impl ErrorHandlers {
+    fn new() -> ErrorHandlers {
+        ErrorHandlers { not_found: None, error: None }
+    }
+
+    // the handler of an error, the not found handler is used for NotFound
+    // errors when it is set
+    fn find<'a>(&'a self, e: &DispatchError) -> Option<&'a Box<ErrorHandler + Send + Sync>> {
+        match *e {
+            NotFound(_) if self.not_found.is_some() => self.not_found.as_ref(),
+            _                                       => self.error.as_ref()
+        }
+    }
+}
+
+/// A dispatcher which can be mounted inside another one, whatever its
+/// user_params and its producer
+pub trait Dispatch {
+    /// Dispatch a request, see `Dispatcher::run_request`
+    fn run_request(&self,
+                   route: &str,
+                   request: &mut Request)
+                   -> Result<Box<Response>, DispatchError>;
+    /// Dispatch a request with a normalised path for the dispatcher it is
+    /// mounted in, like `Dispatcher::run_normalized` without the automatic
+    /// OPTIONS answer which is left to the parent
+    fn run_mounted(&self,
+                   path: &str,
+                   request: &mut Request)
+                   -> Result<Box<Response>, DispatchError>;
+    /// Return the methods allowed for a route, see `Dispatcher::allowed_methods`
+    fn allowed_methods(&self, route: &str) -> Vec<Method>;
+}
+
+/// How the dispatcher treats a path ending with a slash, the routes are
+/// always registered without their trailing slash
+#[deriving(Clone, Show, PartialEq)]
+pub enum TrailingSlash {
+    /// `/users/` is dispatched like `/users`
+    IgnoreTrailingSlash,
+    /// `/users/` is not found
+    StrictTrailingSlash,
+    /// `/users/` is redirected to `/users`, with a 301 for GET and HEAD
+    /// requests and a 308 for the others methods
+    RedirectTrailingSlash
 }
 
 /// The web dispatcher
 pub struct Dispatcher<U = (), P = UnusedProducer> {
     routes: HashMap<(String, Method), RouteDatas<U>>,
-    producer: P
+    tree: Node<((String, Method), uint)>,
+    producer: P,
+    // the producers of the groups
+    producers: Vec<P>,
+    constraints: HashMap<String, String>,
+    names: HashMap<String, String>,
+    mounts: Vec<(String, Box<Dispatch + Send + Sync>)>,
+    middlewares: Vec<Box<Middleware<U> + Send + Sync>>,
+    error_handlers: ErrorHandlers,
+    // the error handlers of the groups, with their prefix
+    group_error_handlers: Vec<(String, ErrorHandlers)>,
+    head_fallback: bool,
+    auto_options: bool,
+    keep_encoded_slashes: bool,
+    trailing_slash: TrailingSlash
 }
 
 impl<U = (), P: Producer<U> + Default = UnusedProducer> Dispatcher<U, P> {
     pub fn new(routes: &[(RoutesFnType<U>, &str, &str, Vec<&str>, &str)]) -> Dispatcher<U, P> {
-        Dispatcher {
-            routes: routes.iter().fold(HashMap::new(), |mut h, &(f, r, m, ref vars, matcher)| {
-                let d = RouteDatas {
-                    var_names: vars.iter().map(|v| v.to_string()).collect(),
-                    regex: Regex::new(matcher).unwrap(),
-                    f: f
-                };
-                h.insert((r.to_string(), from_str(m).unwrap()), d); h
-            }),
-            producer: Default::default()
+        let mut d = Dispatcher::empty(Default::default());
+        for &(f, r, m, _, _) in routes.iter() {
+            d.add(f, r, from_str(m).unwrap());
         }
+        d
     }
 
     pub fn new_with_producer(routes: &[(RoutesFnType<U>, &str, &str, Vec<&'static str>, &str)],
                              producer: P) -> Dispatcher<U, P> {
+        let mut d = Dispatcher::empty(producer);
+        for &(f, r, m, _, _) in routes.iter() {
+            d.add(f, r, from_str(m).unwrap());
+        }
+        d
+    }
+
+    fn empty(producer: P) -> Dispatcher<U, P> {
         Dispatcher {
-            routes: routes.iter().fold(HashMap::new(), |mut h, &(f, r, m, ref vars, matcher)| {
-                let d = RouteDatas {
-                    var_names: vars.iter().map(|v| v.to_string()).collect(),
-                    regex: Regex::new(matcher).unwrap(),
-                    f: f
-                };
-                h.insert((r.to_string(), from_str(m).unwrap()), d); h
-            }),
-            producer: producer
+            routes: HashMap::new(),
+            tree: Node::new(),
+            producer: producer,
+            producers: Vec::new(),
+            constraints: default_constraints(),
+            names: HashMap::new(),
+            mounts: Vec::new(),
+            middlewares: Vec::new(),
+            error_handlers: ErrorHandlers::new(),
+            group_error_handlers: Vec::new(),
+            head_fallback: true,
+            auto_options: true,
+            keep_encoded_slashes: false,
+            trailing_slash: IgnoreTrailingSlash
         }
     }
 
@@ -83,128 +216,744 @@ impl<U = (), P: Producer<U> + Default = UnusedProducer> Dispatcher<U, P> {
         self.producer = param_producer
     }
 
-    pub fn add(&mut self,
-               func: RoutesFnType<U>,
-               route: &str,
-               method: Method) {
+    /// Dispatch HEAD requests to the GET route when there is no HEAD route
+    pub fn set_head_fallback(&mut self, enabled: bool) {
+        self.head_fallback = enabled
+    }
+
+    /// Answer OPTIONS requests when there is no OPTIONS route
+    pub fn set_auto_options(&mut self, enabled: bool) {
+        self.auto_options = enabled
+    }
+
+    /// Keep the encoded slashes `%2F` of the paths encoded in their segments,
+    /// by default the paths containing an encoded slash are rejected
+    pub fn set_keep_encoded_slashes(&mut self, enabled: bool) {
+        self.keep_encoded_slashes = enabled
+    }
+
+    /// Set how the paths ending with a slash are dispatched, they are
+    /// dispatched like the paths without it by default
+    pub fn set_trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy
+    }
+
+    /// Register a named constraint, usable in the routes added afterwards,
+    /// e.g `dispatcher.add_constraint("hex", "[0-9a-f]+")` for `/color/:c<hex>`.
+    ///
+    /// The routes given to `new` or `new_with_producer` are added before any
+    /// constraint can be registered, they can only use the default
+    /// constraints, see `router::default_constraints`.
+    pub fn add_constraint(&mut self, name: &str, pattern: &str) {
+        self.constraints.insert(name.to_string(), pattern.to_string());
+    }
+
+    /// Add a route, fail if the route pattern is invalid
+    pub fn add<H: Handler<U> + Send + Sync>(&mut self,
+                                        func: H,
+                                        route: &str,
+                                        method: Method) {
+        match self.try_add(func, route, method) {
+            Ok(_)  => {},
+            Err(e) => fail!("{}", e)
+        }
+    }
+
+    /// Add a route, return an error if the route pattern is invalid
+    pub fn try_add<H: Handler<U> + Send + Sync>(&mut self,
+                                            func: H,
+                                            route: &str,
+                                            method: Method)
+                                            -> Result<(), DispatchError> {
+        self.try_add_route(box func, route, method, Vec::new(), None)
+    }
+
+    /// Add a group of routes sharing a prefix, e.g
+    ///
+    ///```Rust
+    /// dispatcher.group("/api/v1", |g| {
+    ///     g.hook(check_token);
+    ///     g.add(list_users, "/users", Get);
+    ///     g.add(get_user, "/users/:id<int>", Get);
+    /// });
+    ///```
+    pub fn group(&mut self, prefix: &str, f: |&mut Group<U, P>|) {
+        let routes = {
+            let mut g = Group::new(self, join_prefix("", prefix));
+            f(&mut g);
+            g.close()
+        };
+        for r in routes.move_iter() {
+            self.add_group_route(r);
+        }
+    }
+
+    fn add_group_route(&mut self, r: GroupRoute<U>) {
+        let GroupRoute { func, route, method, name, hooks, producer } = r;
+        match self.try_add_route(func, route.as_slice(), method, hooks, producer) {
+            Ok(_)  => {},
+            Err(e) => fail!("{}", e)
+        }
+        match name {
+            Some(name) => self.name_route(name.as_slice(), route.as_slice()),
+            None       => {}
+        }
+    }
+
+    fn try_add_route(&mut self,
+                     func: Box<Handler<U> + Send + Sync>,
+                     route: &str,
+                     method: Method,
+                     hooks: Vec<HookFnType>,
+                     producer: Option<uint>)
+                     -> Result<(), DispatchError> {
         let clean_route = remove_trailling_slash(route);
-        self.routes.insert((clean_route.to_string(), method),
+        let mut forms = Vec::new();
+        for r in try!(expand_route(clean_route.as_slice())).iter() {
+            forms.push(try!(parse_route(r.as_slice(), &self.constraints)));
+        }
+
+        let key = (clean_route.clone(), method);
+        // a route added again keeps its registration order
+        let index = match self.routes.find(&key) {
+            Some(d) => d.index,
+            None    => self.routes.len()
+        };
+        let mut defaults = HashMap::new();
+        for (i, segments) in forms.iter().enumerate() {
+            self.tree.insert(segments.as_slice(), (key.clone(), i));
+            for s in segments.iter() {
+                match *s {
+                    Var(ref name, _, Some(ref value)) => {
+                        defaults.insert(name.clone(), value.clone());
+                    },
+                    _ => {}
+                }
+            }
+        }
+        self.routes.insert(key,
                            RouteDatas {
-                               var_names: create_vars_regex_vec(clean_route.as_slice()),
-                               regex: create_match_regex(clean_route.as_slice()),
+                               variants: forms.iter().map(|segments| RouteVariant {
+                                   var_names: capture_names(segments.as_slice()),
+                                   specificity: Specificity::new(segments.as_slice())
+                               }).collect(),
+                               defaults: defaults,
+                               index: index,
+                               hooks: hooks,
+                               middlewares: Vec::new(),
+                               producer: producer,
                                f: func
                            });
+        Ok(())
+    }
+
+    /// Set the handler building the response of the requests matching no
+    /// route, used by `respond`
+    pub fn set_not_found<H: ErrorHandler + Send + Sync>(&mut self, handler: H) {
+        self.error_handlers.not_found = Some(box handler as Box<ErrorHandler + Send + Sync>);
+    }
+
+    /// Set the handler building the response of the requests failing with
+    /// any error, except the `NotFound` errors when a not found handler is
+    /// set, used by `respond`
+    pub fn set_error_handler<H: ErrorHandler + Send + Sync>(&mut self, handler: H) {
+        self.error_handlers.error = Some(box handler as Box<ErrorHandler + Send + Sync>);
+    }
+
+    // the error handlers of a group, created on first use. The prefix is
+    // kept without its trailing slash, the root prefix is empty and matches
+    // all the paths
+    fn group_error_handlers<'a>(&'a mut self, prefix: &str) -> &'a mut ErrorHandlers {
+        let prefix = remove_trailling_slash(prefix);
+        let prefix = prefix.as_slice();
+        let pos = self.group_error_handlers.iter().position(|&(ref p, _)| p.as_slice() == prefix);
+        let i = match pos {
+            Some(i) => i,
+            None    => {
+                self.group_error_handlers.push((prefix.to_string(), ErrorHandlers::new()));
+                self.group_error_handlers.len() - 1
+            }
+        };
+        match *self.group_error_handlers.get_mut(i) {
+            (_, ref mut handlers) => handlers
+        }
+    }
+
+    /// Add a middleware run around all the routes of the dispatcher, see the
+    /// `middleware` module
+    pub fn add_middleware<M: Middleware<U> + Send + Sync>(&mut self, middleware: M) {
+        self.middlewares.push(box middleware as Box<Middleware<U> + Send + Sync>);
+    }
+
+    /// Add a middleware run around a single route, after the middlewares of
+    /// the dispatcher. Fail if the route has not been added.
+    pub fn add_route_middleware<M: Middleware<U> + Send + Sync>(&mut self,
+                                                              route: &str,
+                                                              method: Method,
+                                                              middleware: M) {
+        let key = (remove_trailling_slash(route), method);
+        match self.routes.find_mut(&key) {
+            Some(d) => d.middlewares.push(box middleware as Box<Middleware<U> + Send + Sync>),
+            None    => fail!("{}", NotFound(route.to_string()))
+        }
+    }
+
+    /// Mount a dispatcher under a prefix: the routes starting with the prefix
+    /// are dispatched to `sub` without the prefix, if `sub` has no route for
+    /// the path and the method this dispatcher tries its own routes
+    pub fn mount<D: Dispatch + Send + Sync>(&mut self, prefix: &str, sub: D) {
+        let sub = box sub as Box<Dispatch + Send + Sync>;
+        self.mounts.push((remove_trailling_slash(prefix), sub));
+    }
+
+    /// Add a route and give it a name, used to build its urls with `url_for`
+    pub fn add_named<H: Handler<U> + Send + Sync>(&mut self,
+                                              name: &str,
+                                              func: H,
+                                              route: &str,
+                                              method: Method) {
+        self.add(func, route, method);
+        self.name_route(name, route);
     }
 
-    pub fn run_request(&mut self,
+    /// Give a name to a route, e.g a route added by `new` with `routes!()`
+    pub fn name_route(&mut self, name: &str, route: &str) {
+        self.names.insert(name.to_string(), remove_trailling_slash(route));
+    }
+
+    /// Build the url of a named route, using `params` for its variables, its
+    /// anonymous wildcards (`*0`, `*1`...) and its catch-all.
+    ///
+    /// The optional parts of the route are kept as long as their parameters
+    /// are given.
+    pub fn url_for(&self,
+                   name: &str,
+                   params: &HashMap<String, String>)
+                   -> Result<String, DispatchError> {
+        let route = match self.names.find_equiv(&name) {
+            Some(r) => r,
+            None    => return Err(UnknownRoute(name.to_string()))
+        };
+
+        // the forms of the route are tried from the longest to the shortest
+        let mut error = None;
+        for form in try!(expand_route(route.as_slice())).iter() {
+            let segments = try!(parse_route(form.as_slice(), &self.constraints));
+            match build_url(segments.as_slice(), params) {
+                Ok(url)                       => return Ok(url),
+                Err(e @ MissingParameter(_))  => error = Some(e),
+                Err(e)                        => return Err(e)
+            }
+        }
+        Err(error.unwrap())
+    }
+
+    /// Dispatch a request, `route` is the request target, only its path is
+    /// used to find the route, once normalised
+    pub fn run_request(&self,
                        route: &str,
                        request: &mut Request)
-                       -> Result<Box<Response>, String> {
-        match self.find_simple_hash_route(route, request) {
-            Some(r) => Ok(r),
-            None    => {
-                match self.find_complex_route(route, request) {
-                    Some(r) => Ok(r),
-                    None    => Err(format!("route: {}, don't exist", route))
+                       -> Result<Box<Response>, DispatchError> {
+        let (route, query) = split_target(route);
+        request.set_raw_path(route);
+        request.set_query_params(query.map(|q| parse_query(q)).unwrap_or(HashMap::new()));
+        let path = match normalize_path(route, self.keep_encoded_slashes) {
+            Some(path) => path,
+            None       => return Err(InvalidPath(route.to_string()))
+        };
+        if route.len() > 1 && route.ends_with("/") {
+            match self.trailing_slash {
+                IgnoreTrailingSlash => {},
+                StrictTrailingSlash => return Err(NotFound(route.to_string())),
+                RedirectTrailingSlash => {
+                    let allowed = self.allowed_methods(path.as_slice());
+                    if allowed.is_empty() {
+                        return Err(NotFound(route.to_string()));
+                    }
+                    // a method not allowed is answered without a redirection
+                    let method = request.method();
+                    if allowed.contains(&method) {
+                        let r = canonical_redirect(path.as_slice(), query, &method);
+                        return Ok(box r as Box<Response>);
+                    }
                 }
             }
         }
+        self.run_normalized(path.as_slice(), request)
     }
 
-    pub fn run(&mut self,
-               route: &str,
-               mut web_params: HashMap<String, String>)
-               -> Result<Box<Response>, String> {
-        self.run_request(route, &mut web_params as &mut Request)
+    /// Dispatch a request whose path is already normalised, the path is not
+    /// decoded again
+    pub fn run_normalized(&self,
+                          route: &str,
+                          request: &mut Request)
+                          -> Result<Box<Response>, DispatchError> {
+        self.run_path(route, request, self.auto_options)
     }
 
-    fn find_simple_hash_route(&mut self,
+    // dispatch a normalised path, the automatic OPTIONS answer of a mounted
+    // dispatcher is disabled so that its parent answers with the methods of
+    // both dispatchers
+    fn run_path(&self,
+                route: &str,
+                request: &mut Request,
+                auto_options: bool)
+                -> Result<Box<Response>, DispatchError> {
+        for &(ref prefix, ref sub) in self.mounts.iter() {
+            match strip_prefix(prefix.as_slice(), route) {
+                // the parent may have a route for the method, its allowed
+                // methods include the ones of the mounted dispatcher
+                Some(sub_route) => match sub.run_mounted(sub_route, request) {
+                    Err(NotFound(_))              => {},
+                    Err(MethodNotAllowed { .. }) => {},
+                    r                             => return r
+                },
+                None => {}
+            }
+        }
+
+        let method = request.method();
+        match self.dispatch(route, &method, request) {
+            Some(r) => return outcome(route, r),
+            None    => {}
+        }
+        if method == Head && self.head_fallback {
+            match self.dispatch(route, &Get, request) {
+                Some(r) => {
+                    return outcome(route, r).map(|r| box r.to_http().to_head() as Box<Response>)
+                },
+                None => {}
+            }
+        }
+
+        let allowed = self.allowed_methods(route);
+        if allowed.is_empty() {
+            Err(NotFound(route.to_string()))
+        } else if method == Options && auto_options {
+            let r = HttpResponse::new(200).with_header("Allow", allow_header(&allowed).as_slice());
+            Ok(box r as Box<Response>)
+        } else {
+            Err(MethodNotAllowed { allowed: allowed })
+        }
+    }
+
+    /// Dispatch a request and return its http response. The errors of the
+    /// dispatcher are converted by the error handlers of the innermost group
+    /// matching the path, else by the error handlers of the dispatcher, else
+    /// with `DispatchError::to_response`
+    pub fn respond(&self, route: &str, request: &mut Request) -> HttpResponse {
+        match self.run_request(route, request) {
+            Ok(r)  => r.to_http(),
+            Err(e) => self.error_response(route, &*request, &e)
+        }
+    }
+
+    // build the response of a failed request with the error handlers
+    fn error_response(&self, route: &str, request: &Request, e: &DispatchError) -> HttpResponse {
+        let (path, _) = split_target(route);
+        let path = normalize_path(path, self.keep_encoded_slashes).unwrap_or(path.to_string());
+        let group = self.group_error_handlers.iter()
+            .filter(|&&(ref prefix, ref h)| {
+                strip_prefix(prefix.as_slice(), path.as_slice()).is_some() && h.find(e).is_some()
+            })
+            .max_by(|&&(ref prefix, _)| prefix.len());
+        let handlers = match group {
+            Some(&(_, ref h)) => h,
+            None              => &self.error_handlers
+        };
+        match handlers.find(e) {
+            Some(h) => h.handle_error(request, e).to_http(),
+            None    => e.to_response()
+        }
+    }
+
+    /// Dispatch a GET request to a request target, e.g `/search?q=rust`.
+    /// The query string is decoded in the query params of the request and
+    /// `web_params` are given to the route as its path params.
+    pub fn run(&self,
+               target: &str,
+               web_params: HashMap<String, String>)
+               -> Result<Box<Response>, DispatchError> {
+        let mut request = WebRequest::new(Get, target);
+        request.add_params(web_params);
+        self.run_request(target, &mut request as &mut Request)
+    }
+
+    /// Return the methods for which a route match the given path
+    pub fn allowed_methods(&self, route: &str) -> Vec<Method> {
+        let path = split_path(route);
+        let mut allowed = Vec::new();
+        for &(&((_, ref m), _), _) in self.tree.find(path.as_slice()).iter() {
+            if !allowed.contains(m) {
+                allowed.push(m.clone());
+            }
+        }
+        for &(ref prefix, ref sub) in self.mounts.iter() {
+            match strip_prefix(prefix.as_slice(), route) {
+                Some(sub_route) => {
+                    for m in sub.allowed_methods(sub_route).move_iter() {
+                        if !allowed.contains(&m) {
+                            allowed.push(m);
+                        }
+                    }
+                },
+                None => {}
+            }
+        }
+        if self.head_fallback && allowed.contains(&Get) && !allowed.contains(&Head) {
+            allowed.push(Head);
+        }
+        if self.auto_options && !allowed.is_empty() && !allowed.contains(&Options) {
+            allowed.push(Options);
+        }
+        allowed
+    }
+
+    fn dispatch(&self,
+                route: &str,
+                method: &Method,
+                request: &mut Request)
+                -> Option<Resp<Box<Response>>> {
+        match self.find_simple_hash_route(route, method, request) {
+            Some(r) => Some(r),
+            None    => self.find_complex_route(route, method, request)
+        }
+    }
+
+    fn find_simple_hash_route(&self,
                              route: &str,
+                             method: &Method,
                              request: &mut Request)
-                             -> Option<Box<Response>> {
-        match self.routes.find(&(route.to_string(), request.method())) {
-            Some(f) => Some((f.f)(request, self.producer.get_new())),
-            None     => None
+                             -> Option<Resp<Box<Response>>> {
+        match self.routes.find(&(route.to_string(), method.clone())) {
+            Some(d) if d.is_static() => Some(self.call(d, route, method, route, request)),
+            _                        => None
         }
     }
 
-    fn find_complex_route(&mut self,
+    fn find_complex_route(&self,
                           route: &str,
+                          method: &Method,
                           request: &mut Request)
-                          -> Option<Box<Response>> {
-        let mut result = None;
-        for (&(_, m), d) in self.routes.iter() {
-            if m == request.method() {
-                if d.regex.is_match(route) {
-                    let mut new_params: HashMap<String, String> = HashMap::new();
-                    if d.var_names.len() > 0 {
-                        let c = d.regex.captures(route).unwrap();
-                        let mut i = c.iter();
-                        i.next();
-                        d.var_names.iter().zip(i).advance(|(a, b)| {
-                            new_params.insert(a.to_string(), b.to_string());
-                            true
-                        });
+                          -> Option<Resp<Box<Response>>> {
+        let path = split_path(route);
+        let found = self.tree.find(path.as_slice());
+        let best = found.iter()
+            .filter(|&&(&(ref key, _), _)| { let (_, ref m) = *key; m == method })
+            .map(|&(&(ref key, i), ref captures)| {
+                let d = self.routes.find(key).unwrap();
+                (key, d, &d.variants[i], captures)
+            })
+            .min_by(|&(_, d, v, _)| (v.specificity.clone(), d.index));
+
+        match best {
+            Some((&(ref pattern, _), d, v, captures)) => {
+                let mut new_params: HashMap<String, String> = HashMap::new();
+                for (name, value) in v.var_names.iter().zip(captures.iter()) {
+                    new_params.insert(name.clone(), value.clone());
+                }
+                // the absent optional variables take their default value
+                for (name, value) in d.defaults.iter() {
+                    if !new_params.contains_key(name) {
+                        new_params.insert(name.clone(), value.clone());
                     }
-                    request.add_params(new_params);
-                    result = Some((d.f)(request, self.producer.get_new()));
-                    break;
                 }
-            }
+                request.add_params(new_params);
+                Some(self.call(d, pattern.as_slice(), method, route, request))
+            },
+            None => None
         }
+    }
 
-        result
+    // run the middlewares, the hooks then the function of a route
+    fn call(&self,
+            d: &RouteDatas<U>,
+            pattern: &str,
+            method: &Method,
+            path: &str,
+            request: &mut Request)
+            -> Resp<Box<Response>> {
+        let u = match d.producer {
+            Some(i) => self.producers[i].get_new(),
+            None    => self.producer.get_new()
+        };
+        let info = RouteInfo { route: pattern, method: method, path: path };
+        let middlewares: Vec<&Middleware<U>> = self.middlewares.iter()
+            .chain(d.middlewares.iter())
+            .map(|m| &**m as &Middleware<U>)
+            .collect();
+        run_chain(middlewares.as_slice(), d.hooks.as_slice(), &*d.f, &info, u, request)
     }
 }
 
-fn remove_trailling_slash(route: &str) -> String {
-    let mut r = route.to_string();
-    let mut len = r.len() - 1u;
-    while r.len() > 0 && r.as_bytes()[len] == '/' as u8 {
-        r.pop_char();
-        len -= 1;
+impl<U, P: Producer<U> + Default> Dispatch for Dispatcher<U, P> {
+    fn run_request(&self,
+                   route: &str,
+                   request: &mut Request)
+                   -> Result<Box<Response>, DispatchError> {
+        self.run_request(route, request)
     }
+
+    fn run_mounted(&self,
+                   path: &str,
+                   request: &mut Request)
+                   -> Result<Box<Response>, DispatchError> {
+        self.run_path(path, request, false)
+    }
+
+    fn allowed_methods(&self, route: &str) -> Vec<Method> {
+        self.allowed_methods(route)
+    }
+}
+
+// redirect a path ending with a slash to its canonical form, built from the
+// normalised path, the query is kept
+fn canonical_redirect(path: &str, query: Option<&str>, method: &Method) -> HttpResponse {
+    let segments: Vec<String> = split_path(path).iter().map(|s| {
+        // the encoded slashes kept by the normalisation are already encoded
+        let pieces: Vec<String> = s.split_str("%2F").map(|p| percent_encode(p)).collect();
+        pieces.connect("%2F")
+    }).collect();
+    let mut location = format!("/{}", segments.connect("/"));
+    match query {
+        Some(q) => location.push_str(format!("?{}", q).as_slice()),
+        None    => {}
+    }
+    let status = if *method == Get || *method == Head { 301 } else { 308 };
+    HttpResponse::redirect(location.as_slice()).with_status(status)
+}
+
+// convert the outcome of a route into the result of the dispatcher
+fn outcome(route: &str, r: Resp<Box<Response>>) -> Result<Box<Response>, DispatchError> {
+    match r {
+        Filled(r)          => Ok(r),
+        NoResp             => Ok(box HttpResponse::new(204) as Box<Response>),
+        InternalError(msg) => Err(HandlerFailed(msg)),
+        RoutingError(_)    => Err(NotFound(route.to_string()))
+    }
+}
+
+// a route waiting for its group to be closed
+struct GroupRoute<U> {
+    func: Box<Handler<U> + Send + Sync>,
+    route: String,
+    method: Method,
+    name: Option<String>,
+    hooks: Vec<HookFnType>,
+    producer: Option<uint>
+}
+
+/// A group of routes sharing a prefix, created by `Dispatcher::group`.
+///
+/// The hooks and the producer of a group apply to all its routes, including
+/// the routes of its nested groups, the hooks of the outer groups run first.
+/// The error handlers of a group apply to all the paths starting with its
+/// prefix.
+pub struct Group<'a, U, P> {
+    dispatcher: &'a mut Dispatcher<U, P>,
+    prefix: String,
+    hooks: Vec<HookFnType>,
+    producer: Option<uint>,
+    routes: Vec<GroupRoute<U>>
+}
+
+impl<'a, U, P: Producer<U> + Default> Group<'a, U, P> {
+    fn new(dispatcher: &'a mut Dispatcher<U, P>, prefix: String) -> Group<'a, U, P> {
+        Group {
+            dispatcher: dispatcher,
+            prefix: prefix,
+            hooks: Vec::new(),
+            producer: None,
+            routes: Vec::new()
+        }
+    }
+
+    /// Add a route to the group, the prefix of the group is prepended to `route`
+    pub fn add<H: Handler<U> + Send + Sync>(&mut self, func: H, route: &str, method: Method) {
+        self.add_route(box func, route, method, None)
+    }
+
+    /// Add a named route to the group, see `Dispatcher::add_named`
+    pub fn add_named<H: Handler<U> + Send + Sync>(&mut self,
+                                              name: &str,
+                                              func: H,
+                                              route: &str,
+                                              method: Method) {
+        self.add_route(box func, route, method, Some(name.to_string()))
+    }
+
+    /// Add a hook, run before the functions of all the routes of the group
+    pub fn hook(&mut self, hook: HookFnType) {
+        self.hooks.push(hook)
+    }
+
+    /// Set the not found handler of the requests matching the prefix of the
+    /// group, see `Dispatcher::set_not_found`
+    pub fn set_not_found<H: ErrorHandler + Send + Sync>(&mut self, handler: H) {
+        let handlers = self.dispatcher.group_error_handlers(self.prefix.as_slice());
+        handlers.not_found = Some(box handler as Box<ErrorHandler + Send + Sync>);
+    }
+
+    /// Set the error handler of the requests matching the prefix of the
+    /// group, see `Dispatcher::set_error_handler`
+    pub fn set_error_handler<H: ErrorHandler + Send + Sync>(&mut self, handler: H) {
+        let handlers = self.dispatcher.group_error_handlers(self.prefix.as_slice());
+        handlers.error = Some(box handler as Box<ErrorHandler + Send + Sync>);
+    }
+
+    /// Set the producer used by the routes of the group
+    pub fn set_producer(&mut self, producer: P) {
+        self.dispatcher.producers.push(producer);
+        self.producer = Some(self.dispatcher.producers.len() - 1);
+    }
+
+    /// Add a nested group, its prefix is appended to the prefix of this group
+    pub fn group(&mut self, prefix: &str, f: |&mut Group<U, P>|) {
+        let routes = {
+            let mut g = Group::new(&mut *self.dispatcher,
+                                   join_prefix(self.prefix.as_slice(), prefix));
+            f(&mut g);
+            g.close()
+        };
+        self.routes.push_all_move(routes);
+    }
+
+    fn add_route(&mut self,
+                 func: Box<Handler<U> + Send + Sync>,
+                 route: &str,
+                 method: Method,
+                 name: Option<String>) {
+        self.routes.push(GroupRoute {
+            func: func,
+            route: join_prefix(self.prefix.as_slice(), route),
+            method: method,
+            name: name,
+            hooks: Vec::new(),
+            producer: None
+        })
+    }
+
+    // apply the hooks and the producer of the group to its routes
+    fn close(self) -> Vec<GroupRoute<U>> {
+        let Group { hooks, producer, routes, .. } = self;
+        routes.move_iter().map(|mut r| {
+            let mut route_hooks: Vec<HookFnType> = hooks.iter().map(|h| *h).collect();
+            route_hooks.push_all_move(r.hooks);
+            r.hooks = route_hooks;
+            r.producer = r.producer.or(producer);
+            r
+        }).collect()
+    }
+}
+
+// remove the prefix of a mounted dispatcher from a route
+fn strip_prefix<'a>(prefix: &str, route: &'a str) -> Option<&'a str> {
+    if !route.starts_with(prefix) {
+        return None;
+    }
+    let rest = route.slice_from(prefix.len());
+    if rest.is_empty() || rest.starts_with("/") {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+// append a route to the prefix of a group
+fn join_prefix(prefix: &str, route: &str) -> String {
+    let mut r = remove_trailling_slash(prefix);
+    if !route.is_empty() && !route.starts_with("/") {
+        r.push_char('/');
+    }
+    r.push_str(route);
     r
 }
 
-// create a Vec which contains all the captures names for a route
-fn create_vars_regex_vec(route: &str) -> Vec<String> {
-    let vars_regex = create_vars_regex(route);
-    let re = Regex::new(vars_regex.as_slice()).unwrap();
-    match re.captures(route) {
-        Some(c) => {
-            let mut cap_i = c.iter();
-            cap_i.next();
-            cap_i.map(|x| {x.to_string()}).collect()
+// create a Vec which contains the name of the captures of a route
+fn capture_names(segments: &[Segment]) -> Vec<String> {
+    let mut wildcards = 0u;
+    segments.iter().filter_map(|s| match *s {
+        Var(ref name, _, _) | CatchAll(ref name) => Some(name.clone()),
+        Wildcard => {
+            wildcards += 1;
+            Some(format!("*{}", wildcards - 1))
         },
-        None => Vec::new()
-    }
+        _ => None
+    }).collect()
 }
 
-// create the regex to captures the vars in the route
-fn create_vars_regex(r: &str) -> String {
-    let mut var_reg: String = String::from_char(1, '^');
-    var_reg = var_reg.append(RE_VAR.replace_all(r, ":([0-9a-zA-Z-_]+)").as_slice());
-    var_reg = var_reg.replace("*", "\\*");
-    var_reg.push_str("/??$");
-    var_reg
+// build the url of a route from the given params
+fn build_url(segments: &[Segment],
+             params: &HashMap<String, String>)
+             -> Result<String, DispatchError> {
+    let mut url = String::new();
+    let mut wildcards = 0u;
+    for s in segments.iter() {
+        url.push_char('/');
+        match *s {
+            Static(ref name) => url.push_str(name.as_slice()),
+            Var(ref name, ref constraint, _) => {
+                let value = match params.find(name) {
+                    Some(v) if !v.is_empty() => v,
+                    _                        => return Err(MissingParameter(name.clone()))
+                };
+                match *constraint {
+                    Some(ref c) if !c.is_match(value.as_slice()) => {
+                        return Err(InvalidParameter(name.clone()))
+                    },
+                    _ => {}
+                }
+                url.push_str(percent_encode(value.as_slice()).as_slice());
+            },
+            Wildcard => {
+                let name = format!("*{}", wildcards);
+                wildcards += 1;
+                match params.find(&name) {
+                    Some(v) => url.push_str(percent_encode(v.as_slice()).as_slice()),
+                    None    => return Err(MissingParameter(name))
+                }
+            },
+            CatchAll(ref name) => {
+                match params.find(name) {
+                    Some(v) => {
+                        let parts: Vec<String> = v.as_slice().split('/')
+                            .map(|p| percent_encode(p))
+                            .collect();
+                        url.push_str(parts.connect("/").as_slice());
+                    },
+                    None => return Err(MissingParameter(name.clone()))
+                }
+            }
+        }
+    }
+
+    if url.is_empty() {
+        url.push_char('/');
+    }
+    Ok(url)
 }
 
-// crete the matching regex to recognize the routes
-fn create_match_regex(r: &str) -> Regex {
-    let mut match_reg: String = String::from_char(1, '^');
-    match_reg = match_reg.append(RE_VAR.replace_all(r, "([0-9a-zA-Z-_]+)").as_slice());
-    match_reg = match_reg.replace("*", "[0-9a-zA-Z-_]*");
-    match_reg.push_str("/??$");
-    Regex::new(match_reg.as_slice()).unwrap()
+fn remove_trailling_slash(route: &str) -> String {
+    let mut r = route.to_string();
+    let mut len = r.len() - 1u;
+    while r.len() > 0 && r.as_bytes()[len] == '/' as u8 {
+        r.pop_char();
+        len -= 1;
+    }
+    r
 }
 
 impl<U, P> Show for Dispatcher<U, P> {
      fn fmt(&self, f: &mut Formatter) ->  Result<(), FormatError> {
         let mut to_write = String::from_str("Dispatcher {\n");
-        for (&(ref r, m), _) in self.routes.iter() {
+        let mut routes: Vec<(&(String, Method), &RouteDatas<U>)> = self.routes.iter().collect();
+        routes.sort_by(|&(_, a), &(_, b)| a.index.cmp(&b.index));
+        for &(&(ref r, ref m), _) in routes.iter() {
             to_write.push_str(format!("  {} {}\n", m, r).as_slice());
         }
+        for &(ref prefix, _) in self.mounts.iter() {
+            to_write.push_str(format!("  MOUNT {}\n", prefix).as_slice());
+        }
         write!(f, "{}}}\n", to_write)
      }
 }
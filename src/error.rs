@@ -0,0 +1,121 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Errors returned by the web dispatcher
+
+use std::fmt::{Show, Formatter, FormatError};
+
+use method::Method;
+use response::HttpResponse;
+
+/// Errors returned when a request cannot be dispatched
+#[deriving(Clone, PartialEq)]
+pub enum DispatchError {
+    /// No route match the path of the request
+    NotFound(String),
+    /// The path is known, but not for the method of the request
+    MethodNotAllowed {
+        /// the methods registered for this path
+        allowed: Vec<Method>
+    },
+    /// A route pattern cannot be parsed
+    InvalidPattern(String),
+    /// The route has been found but the user function has failed
+    HandlerFailed(String),
+    /// No route has been registered with this name
+    UnknownRoute(String),
+    /// A parameter needed to build an url is missing
+    MissingParameter(String),
+    /// A parameter used to build an url doesn't satisfy its constraint
+    InvalidParameter(String),
+    /// The path of the request is rejected by its normalisation, e.g it
+    /// contains an encoded slash or invalid utf-8
+    InvalidPath(String)
+}
+
+impl DispatchError {
+    /// A short description of the error
+    pub fn description(&self) -> &'static str {
+        match *self {
+            NotFound(_)              => "route not found",
+            MethodNotAllowed { .. }  => "method not allowed",
+            InvalidPattern(_)        => "invalid route pattern",
+            HandlerFailed(_)         => "route handler failed",
+            UnknownRoute(_)          => "unknown route name",
+            MissingParameter(_)      => "missing url parameter",
+            InvalidParameter(_)      => "invalid url parameter",
+            InvalidPath(_)           => "invalid request path"
+        }
+    }
+}
+
+impl DispatchError {
+    /// The http response matching the error, e.g `404 Not Found` for
+    /// `NotFound`, or `405 Method Not Allowed` with the `Allow` header
+    pub fn to_response(&self) -> HttpResponse {
+        match *self {
+            NotFound(_) => HttpResponse::not_found(),
+            InvalidPath(_) => HttpResponse::error(400),
+            MethodNotAllowed { ref allowed } => {
+                HttpResponse::error(405).with_header("Allow", allow_header(allowed).as_slice())
+            },
+            _ => HttpResponse::error(500)
+        }
+    }
+}
+
+/// The value of the `Allow` header for the given methods
+pub fn allow_header(methods: &Vec<Method>) -> String {
+    let names: Vec<String> = methods.iter().map(|m| m.to_string()).collect();
+    names.connect(", ")
+}
+
+impl Show for DispatchError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        match *self {
+            NotFound(ref route) => {
+                write!(f, "route: {}, don't exist", route)
+            },
+            MethodNotAllowed { ref allowed } => {
+                write!(f, "method not allowed, allowed methods: {}", allowed)
+            },
+            InvalidPattern(ref pattern) => {
+                write!(f, "invalid route pattern: {}", pattern)
+            },
+            HandlerFailed(ref msg) => {
+                write!(f, "route handler failed: {}", msg)
+            },
+            UnknownRoute(ref name) => {
+                write!(f, "no route named: {}", name)
+            },
+            MissingParameter(ref name) => {
+                write!(f, "missing url parameter: {}", name)
+            },
+            InvalidParameter(ref name) => {
+                write!(f, "invalid url parameter: {}", name)
+            },
+            InvalidPath(ref path) => {
+                write!(f, "invalid request path: {}", path)
+            }
+        }
+    }
+}
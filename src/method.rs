@@ -23,10 +23,12 @@
 //! Available methods for web requests
 
 use std::from_str::FromStr;
-use std::ascii::OwnedStrAsciiExt;
+use std::ascii::{OwnedStrAsciiExt, StrAsciiExt};
+use std::fmt::{Show, Formatter, FormatError};
+use std::hash::{Hash, Writer};
 
 /// Available methods for web requests
-#[deriving(Clone, Show, Eq, PartialEq, Hash)]
+#[deriving(Clone)]
 pub enum Method {
     /// GET method
     Get,
@@ -39,7 +41,46 @@ pub enum Method {
     /// PUT method
     Put,
     /// CONNECT method
-    Connect
+    Connect,
+    /// OPTIONS method
+    Options,
+    /// TRACE method
+    Trace,
+    /// PATCH method
+    Patch,
+    /// Any other method, e.g `PROPFIND` or `MKCOL`, compared without case,
+    /// see `Method::extension`
+    Extension(String)
+}
+
+impl Method {
+    /// A method from its name, in any case, e.g `propfind` gives
+    /// `Extension("PROPFIND")` and `get` gives `Get`. None if the name is not
+    /// a valid method name.
+    pub fn extension(name: &str) -> Option<Method> {
+        from_str(name)
+    }
+
+    /// The name of the method, as used in a request line
+    pub fn as_str<'a>(&'a self) -> &'a str {
+        match *self {
+            Get              => "GET",
+            Post             => "POST",
+            Head             => "HEAD",
+            Delete           => "DELETE",
+            Put              => "PUT",
+            Connect          => "CONNECT",
+            Options          => "OPTIONS",
+            Trace            => "TRACE",
+            Patch            => "PATCH",
+            Extension(ref m) => m.as_slice()
+        }
+    }
+}
+
+// check if a char can be used in a method name, see RFC 7230 section 3.2.6
+fn is_token_char(c: char) -> bool {
+    c.is_alphanumeric() && c.is_ascii() || "!#$%&'*+-.^_`|~".contains_char(c)
 }
 
 impl FromStr for Method {
@@ -51,7 +92,32 @@ impl FromStr for Method {
             "DELETE" => Some(Delete),
             "PUT" => Some(Put),
             "CONNECT" => Some(Connect),
+            "OPTIONS" => Some(Options),
+            "TRACE" => Some(Trace),
+            "PATCH" => Some(Patch),
+            ""     => None,
+            m if m.chars().all(is_token_char) => Some(Extension(m.to_string())),
             _      => None
         }
     }
-}
\ No newline at end of file
+}
+
+impl PartialEq for Method {
+    fn eq(&self, other: &Method) -> bool {
+        self.as_str().eq_ignore_ascii_case(other.as_str())
+    }
+}
+
+impl Eq for Method {}
+
+impl<S: Writer> Hash<S> for Method {
+    fn hash(&self, state: &mut S) {
+        self.as_str().to_string().into_ascii_upper().hash(state)
+    }
+}
+
+impl Show for Method {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,120 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Middlewares, run around the functions of the routes
+//!
+//! A middleware receives the request, the informations of the matched route
+//! and the rest of the chain, `next`. It can answer by itself without calling
+//! `next`, e.g to reject an unauthorized request, or change the outcome
+//! returned by `next`, e.g to add a header.
+//!
+//! The middlewares of the dispatcher run first, in their registration order,
+//! then the middlewares of the route, then the hooks and the function of the
+//! route.
+
+use method::Method;
+use response::{Request, Response, Resp};
+use tools::{Handler, HookFnType};
+
+/// Informations on the route matched by a request
+pub struct RouteInfo<'a> {
+    /// the route as registered, e.g `/users/:id`
+    pub route: &'a str,
+    /// the method of the route, GET for a HEAD request dispatched to a GET route
+    pub method: &'a Method,
+    /// the normalised path of the request
+    pub path: &'a str
+}
+
+/// The rest of a middleware chain: the next middlewares, then the hooks and
+/// the function of the route
+pub struct Next<'a, U> {
+    middlewares: &'a [&'a Middleware<U>],
+    hooks: &'a [HookFnType],
+    handler: &'a Handler<U>,
+    route: &'a RouteInfo<'a>,
+    user_params: U
+}
+
+impl<'a, U> Next<'a, U> {
+    /// Run the rest of the chain and return its outcome
+    pub fn run(self, request: &mut Request) -> Resp<Box<Response>> {
+        if self.middlewares.is_empty() {
+            for hook in self.hooks.iter() {
+                (*hook)(&mut *request);
+            }
+            return self.handler.handle(&*request, self.user_params);
+        }
+        let m = self.middlewares[0];
+        let next = Next {
+            middlewares: self.middlewares.slice_from(1),
+            hooks: self.hooks,
+            handler: self.handler,
+            route: self.route,
+            user_params: self.user_params
+        };
+        m.call(request, self.route, next)
+    }
+}
+
+/// A middleware, see the module documentation
+pub trait Middleware<U> {
+    /// Handle a request, `next.run(request)` runs the rest of the chain
+    fn call(&self,
+            request: &mut Request,
+            route: &RouteInfo,
+            next: Next<U>)
+            -> Resp<Box<Response>>;
+}
+
+/// Function signature for a middleware
+pub type MiddlewareFnType<U> = fn(request: &mut Request,
+                                  route: &RouteInfo,
+                                  next: Next<U>) -> Resp<Box<Response>>;
+
+impl<U> Middleware<U> for MiddlewareFnType<U> {
+    fn call(&self,
+            request: &mut Request,
+            route: &RouteInfo,
+            next: Next<U>)
+            -> Resp<Box<Response>> {
+        (*self)(request, route, next)
+    }
+}
+
+/// Run a chain of middlewares around the hooks and the handler of a route
+pub fn run_chain<'a, U>(middlewares: &'a [&'a Middleware<U>],
+                        hooks: &'a [HookFnType],
+                        handler: &'a Handler<U>,
+                        route: &'a RouteInfo<'a>,
+                        user_params: U,
+                        request: &mut Request)
+                        -> Resp<Box<Response>> {
+    let next = Next {
+        middlewares: middlewares,
+        hooks: hooks,
+        handler: handler,
+        route: route,
+        user_params: user_params
+    };
+    next.run(request)
+}
@@ -0,0 +1,242 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Headers of the requests and a ready to use request
+
+use std::collections::HashMap;
+use std::ascii::StrAsciiExt;
+use std::slice::Items;
+
+use method::{Method, Get};
+use response::Request;
+use url::{parse_query, split_target};
+
+/// The headers of a request, the names are case insensitive and a header can
+/// have several values
+#[deriving(Clone, Show, PartialEq, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers {
+            entries: Vec::new()
+        }
+    }
+
+    /// Add a value to a header, keeping its previous values
+    pub fn add(&mut self, name: &str, value: &str) {
+        self.entries.push((name.to_string(), value.to_string()));
+    }
+
+    /// Replace all the values of a header
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.add(name, value);
+    }
+
+    /// Remove all the values of a header
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|&(ref n, _)| !n.as_slice().eq_ignore_ascii_case(name));
+    }
+
+    /// Return the first value of a header
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a str> {
+        self.get_all(name).move_iter().next()
+    }
+
+    /// Return all the values of a header, in the order they have been added
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.entries.iter()
+            .filter(|&&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_slice())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Iterate over all the headers, as `(name, value)`
+    pub fn iter<'a>(&'a self) -> Items<'a, (String, String)> {
+        self.entries.iter()
+    }
+}
+
+/// A request built by the server, with its method, its uri and its headers
+pub struct WebRequest {
+    method: Method,
+    uri: String,
+    raw_path: Option<String>,
+    params: HashMap<String, String>,
+    query: HashMap<String, Vec<String>>,
+    headers: Headers,
+    body: Vec<u8>
+}
+
+impl WebRequest {
+    pub fn new(method: Method, uri: &str) -> WebRequest {
+        WebRequest {
+            method: method,
+            uri: uri.to_string(),
+            raw_path: None,
+            params: HashMap::new(),
+            query: match split_target(uri) {
+                (_, Some(q)) => parse_query(q),
+                (_, None)    => HashMap::new()
+            },
+            headers: Headers::new(),
+            body: Vec::new()
+        }
+    }
+
+    /// A copy of a request, e.g to send it to another task
+    pub fn from_request(r: &Request) -> WebRequest {
+        WebRequest {
+            method: r.method(),
+            uri: r.get_uri(),
+            raw_path: Some(r.raw_path()),
+            params: r.params().clone(),
+            query: r.query_params(),
+            headers: r.headers().map(|h| h.clone()).unwrap_or(Headers::new()),
+            body: r.body().to_vec()
+        }
+    }
+
+    /// Add a header to the request
+    pub fn with_header(mut self, name: &str, value: &str) -> WebRequest {
+        self.headers.add(name, value);
+        self
+    }
+
+    /// Add a path parameter to the request, the query params are read from
+    /// the uri
+    pub fn with_param(mut self, name: &str, value: &str) -> WebRequest {
+        self.params.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Set the body of the request
+    pub fn with_body(mut self, body: Vec<u8>) -> WebRequest {
+        self.body = body;
+        self
+    }
+
+    pub fn headers_mut<'a>(&'a mut self) -> &'a mut Headers {
+        &mut self.headers
+    }
+}
+
+impl Request for WebRequest {
+    fn params<'r>(&'r self) -> &'r HashMap<String, String> {
+        &self.params
+    }
+
+    fn get_uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    fn get_host(&self) -> String {
+        self.headers.get("Host").unwrap_or("").to_string()
+    }
+
+    fn add_params(&mut self, params: HashMap<String, String>) {
+        self.params.extend(params.move_iter());
+    }
+
+    fn set_query_params(&mut self, params: HashMap<String, Vec<String>>) {
+        self.query = params;
+    }
+
+    fn query_params(&self) -> HashMap<String, Vec<String>> {
+        self.query.clone()
+    }
+
+    fn set_raw_path(&mut self, path: &str) {
+        self.raw_path = Some(path.to_string());
+    }
+
+    // the path of the dispatched target, else the path of the uri
+    fn raw_path(&self) -> String {
+        match self.raw_path {
+            Some(ref path) => path.clone(),
+            None           => {
+                let (path, _) = split_target(self.uri.as_slice());
+                path.to_string()
+            }
+        }
+    }
+
+    fn method(&self) -> Method {
+        self.method.clone()
+    }
+
+    fn headers<'r>(&'r self) -> Option<&'r Headers> {
+        Some(&self.headers)
+    }
+
+    fn body<'r>(&'r self) -> &'r [u8] {
+        self.body.as_slice()
+    }
+}
+
+impl Default for WebRequest {
+    fn default() -> WebRequest {
+        WebRequest::new(Get, "/")
+    }
+}
+
+// parse the quality of a media range of an `Accept` header, e.g `q=0.8`
+fn quality(params: &[&str]) -> f32 {
+    params.iter()
+        .filter_map(|p| {
+            let p = p.trim();
+            if p.starts_with("q=") { from_str(p.slice_from(2)) } else { None }
+        })
+        .next()
+        .unwrap_or(1.0)
+}
+
+/// Parse the values of an `Accept` header into media ranges, the preferred first
+pub fn parse_accept(values: &[&str]) -> Vec<String> {
+    let mut ranges: Vec<(f32, uint, String)> = Vec::new();
+    for v in values.iter() {
+        for range in v.split(',') {
+            let parts: Vec<&str> = range.split(';').collect();
+            let media = parts[0].trim();
+            if !media.is_empty() {
+                let q = quality(parts.slice_from(1));
+                let i = ranges.len();
+                ranges.push((q, i, media.to_string()));
+            }
+        }
+    }
+    // the most preferred first, keeping the order of the header on ties
+    ranges.sort_by(|&(qa, ia, _), &(qb, ib, _)| {
+        match qb.partial_cmp(&qa) {
+            Some(Equal) | None => ia.cmp(&ib),
+            Some(o)            => o
+        }
+    });
+    ranges.move_iter().filter(|&(q, _, _)| q > 0.0).map(|(_, _, m)| m).collect()
+}
@@ -23,20 +23,340 @@
 // use std::default::Default;
 use std::collections::HashMap;
 use std::default::Default;
+use std::ascii::StrAsciiExt;
+use serialize::json;
+use serialize::json::{Json, ToJson};
+use body::{Part, media_type, header_param, parse_multipart};
 use method::{Method, Get};
+use request::{Headers, parse_accept};
+use url::{parse_query, split_target};
 
-pub trait Response: Clone + Default {}
+/// The responses returned by the routes
+pub trait Response: Clone + Default {
+    /// Convert the response into an http response, by default an empty
+    /// `200 OK` response
+    fn to_http(&self) -> HttpResponse {
+        HttpResponse::new(200)
+    }
+}
 
 impl Response for () {}
 
+/// An http response, with its status, its headers and its body
+#[deriving(Clone, Show, PartialEq)]
+pub struct HttpResponse {
+    /// the status code, e.g 404
+    pub status: u16,
+    /// the headers, in the order they have been added
+    pub headers: Vec<(String, String)>,
+    /// the body
+    pub body: Vec<u8>
+}
+
+impl HttpResponse {
+    /// Create a response with a status, no headers and an empty body
+    pub fn new(status: u16) -> HttpResponse {
+        HttpResponse {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new()
+        }
+    }
+
+    /// A `200 OK` response with a plain text body
+    pub fn ok(body: &str) -> HttpResponse {
+        HttpResponse::new(200).with_text("text/plain; charset=utf-8", body)
+    }
+
+    /// A `200 OK` response with an html body
+    pub fn html(body: &str) -> HttpResponse {
+        HttpResponse::new(200).with_text("text/html; charset=utf-8", body)
+    }
+
+    /// A `200 OK` response with a json body
+    pub fn json<T: ToJson>(value: &T) -> HttpResponse {
+        let body = value.to_json().to_string();
+        HttpResponse::new(200).with_text("application/json", body.as_slice())
+    }
+
+    /// A `404 Not Found` response
+    pub fn not_found() -> HttpResponse {
+        HttpResponse::error(404)
+    }
+
+    /// A `302 Found` response, redirecting to `location`
+    pub fn redirect(location: &str) -> HttpResponse {
+        HttpResponse::new(302).with_header("Location", location)
+    }
+
+    /// A response with an error status, its body is the reason of the status
+    pub fn error(status: u16) -> HttpResponse {
+        HttpResponse::new(status).with_text("text/plain; charset=utf-8", reason(status))
+    }
+
+    /// Set the status of the response
+    pub fn with_status(mut self, status: u16) -> HttpResponse {
+        self.status = status;
+        self
+    }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the body of the response
+    pub fn with_body(mut self, body: Vec<u8>) -> HttpResponse {
+        self.body = body;
+        self
+    }
+
+    /// Set a text body and its content type
+    pub fn with_text(self, content_type: &str, body: &str) -> HttpResponse {
+        let mut r = self.with_body(body.as_bytes().to_vec());
+        r.set_header("Content-Type", content_type);
+        r
+    }
+
+    /// Return the first value of a header, the name is case insensitive
+    pub fn header<'a>(&'a self, name: &str) -> Option<&'a str> {
+        self.headers.iter()
+            .find(|&&(ref n, _)| n.as_slice().eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_slice())
+    }
+
+    /// Replace all the values of a header
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|&(ref n, _)| !n.as_slice().eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    /// The content type of the response, if any
+    pub fn content_type<'a>(&'a self) -> Option<&'a str> {
+        self.header("Content-Type")
+    }
+
+    /// The response to a HEAD request: the same response without the body,
+    /// but with the length of the body
+    pub fn to_head(&self) -> HttpResponse {
+        let mut r = self.clone();
+        r.set_header("Content-Length", r.body.len().to_string().as_slice());
+        r.body = Vec::new();
+        r
+    }
+}
+
+impl Default for HttpResponse {
+    fn default() -> HttpResponse {
+        HttpResponse::new(200)
+    }
+}
+
+impl Response for HttpResponse {
+    fn to_http(&self) -> HttpResponse {
+        self.clone()
+    }
+}
+
+/// The reason phrase of a status code, e.g `Not Found` for 404
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _   => ""
+    }
+}
+
+/// The params of a request live in three distinct namespaces:
+///
+/// * the path params, captured by the route (and added by the hooks)
+/// * the query params, from the query string of the uri
+/// * the body params, from the fields of a form body
+///
+/// `param` looks up a name in the path, then the query, then the body, so a
+/// value captured by the route can't be shadowed by the client.
 pub trait Request {
+    /// The path params of the request
     fn params<'r>(&'r self) -> &'r HashMap<String, String>;
     fn get_uri(&self) -> String { String::from_str("") }
     fn get_host(&self) -> String { String::from_str("") }
     fn add_params(&mut self, params: HashMap<String, String>);
     fn method(&self) -> Method;
+    /// The headers of the request, None if the request doesn't carry headers
+    fn headers<'r>(&'r self) -> Option<&'r Headers> { None }
+
+    /// The first value of a header, the name is case insensitive
+    fn header<'r>(&'r self, name: &str) -> Option<&'r str> {
+        self.headers().and_then(|h| h.get(name))
+    }
+    /// All the values of a header, the name is case insensitive
+    fn header_values<'r>(&'r self, name: &str) -> Vec<&'r str> {
+        match self.headers() {
+            Some(h) => h.get_all(name),
+            None    => Vec::new()
+        }
+    }
+    /// The `Content-Type` header
+    fn content_type<'r>(&'r self) -> Option<&'r str> { self.header("Content-Type") }
+    /// The `Authorization` header
+    fn authorization<'r>(&'r self) -> Option<&'r str> { self.header("Authorization") }
+    /// The `User-Agent` header
+    fn user_agent<'r>(&'r self) -> Option<&'r str> { self.header("User-Agent") }
+    /// The `Content-Length` header, None if it is absent or invalid
+    fn content_length(&self) -> Option<uint> {
+        self.header("Content-Length").and_then(|l| from_str(l.trim()))
+    }
+    /// The media ranges of the `Accept` headers, the preferred first
+    fn accept(&self) -> Vec<String> {
+        parse_accept(self.header_values("Accept").as_slice())
+    }
+
+    /// The raw body of the request
+    fn body<'r>(&'r self) -> &'r [u8] { &[] }
+
+    /// The fields of an `application/x-www-form-urlencoded` or of a
+    /// `multipart/form-data` body, the uploaded files are not included.
+    /// These fields are distinct from the params of the request.
+    fn form(&self) -> HashMap<String, Vec<String>> {
+        let content_type = self.content_type().map(|c| media_type(c));
+        match content_type.as_ref().map(|c| c.as_slice()) {
+            Some("application/x-www-form-urlencoded") => {
+                parse_query(String::from_utf8_lossy(self.body()).as_slice())
+            },
+            Some("multipart/form-data") => {
+                let mut form: HashMap<String, Vec<String>> = HashMap::new();
+                for p in self.multipart().unwrap_or(Vec::new()).move_iter() {
+                    match (p.name, p.filename) {
+                        (Some(name), None) => {
+                            let value = String::from_utf8_lossy(p.data.as_slice()).into_string();
+                            if !form.contains_key(&name) {
+                                form.insert(name.clone(), Vec::new());
+                            }
+                            form.find_mut(&name).unwrap().push(value);
+                        },
+                        _ => {}
+                    }
+                }
+                form
+            },
+            _ => HashMap::new()
+        }
+    }
+
+    /// The parts of a `multipart/form-data` body, None if the body is not a
+    /// valid multipart body
+    fn multipart(&self) -> Option<Vec<Part>> {
+        let boundary = match self.content_type() {
+            Some(c) if media_type(c).as_slice() == "multipart/form-data" => {
+                header_param(c, "boundary")
+            },
+            _ => None
+        };
+        boundary.and_then(|b| parse_multipart(self.body(), b.as_slice()))
+    }
+
+    /// Set the path as received, called by the dispatcher with the path of
+    /// the dispatched target. By default it is ignored and the raw path is
+    /// read from the uri.
+    fn set_raw_path(&mut self, _path: &str) {}
+
+    /// The path of the uri as received, before its normalisation
+    fn raw_path(&self) -> String {
+        let uri = self.get_uri();
+        let (path, _) = split_target(uri.as_slice());
+        path.to_string()
+    }
+
+    /// The path params of the request, same as `params`
+    fn path_params<'r>(&'r self) -> &'r HashMap<String, String> { self.params() }
+
+    /// Set the query params, called by the dispatcher with the query of the
+    /// dispatched target. By default they are ignored and the query params
+    /// are read from the uri.
+    fn set_query_params(&mut self, _params: HashMap<String, Vec<String>>) {}
+
+    /// The params of the query string, a key can have several values
+    fn query_params(&self) -> HashMap<String, Vec<String>> {
+        let uri = self.get_uri();
+        match split_target(uri.as_slice()) {
+            (_, Some(query)) => parse_query(query),
+            (_, None)        => HashMap::new()
+        }
+    }
+
+    /// The params of the body, i.e the fields of the form
+    fn body_params(&self) -> HashMap<String, Vec<String>> { self.form() }
+
+    /// Look up a param in the path params, then in the query params, then in
+    /// the body params. Only the first value of a multi-valued param is returned.
+    fn param(&self, name: &str) -> Option<String> {
+        let key = name.to_string();
+        match self.path_params().find(&key) {
+            Some(v) => return Some(v.clone()),
+            None    => {}
+        }
+        match self.query_params().find(&key) {
+            Some(v) if !v.is_empty() => return Some(v[0].clone()),
+            _                        => {}
+        }
+        self.body_params().find(&key).and_then(|v| v.as_slice().head().map(|s| s.clone()))
+    }
+
+    /// All the params merged with the precedence of `param`, to be used with
+    /// the conversions of `WebParams`
+    fn all_params(&self) -> HashMap<String, String> {
+        let mut all = HashMap::new();
+        let sources = [self.body_params(), self.query_params()];
+        for source in sources.iter() {
+            for (k, v) in source.iter() {
+                if !v.is_empty() {
+                    all.insert(k.clone(), v[0].clone());
+                }
+            }
+        }
+        for (k, v) in self.path_params().iter() {
+            all.insert(k.clone(), v.clone());
+        }
+        all
+    }
+
+    /// The json body of the request, None if the request has no json content
+    /// type or if the body is not valid json
+    fn json(&self) -> Option<Json> {
+        let is_json = match self.content_type() {
+            Some(c) => {
+                let m = media_type(c);
+                m.as_slice() == "application/json" || m.as_slice().ends_with("+json")
+            },
+            None => false
+        };
+        if is_json {
+            json::from_str(String::from_utf8_lossy(self.body()).as_slice()).ok()
+        } else {
+            None
+        }
+    }
 }
 
+/// A bare map of params, the params captured by the route replace the given
+/// ones. The map has no query params, the query string of the dispatched
+/// target is ignored
 impl Request for HashMap<String, String> {
     fn params<'r>(&'r self) -> &'r HashMap<String, String> {
         self
@@ -49,38 +369,48 @@ impl Request for HashMap<String, String> {
     fn method(&self) -> Method { Get }
 }
 
-// /// Responses returned by the web dispatcher
-// #[deriving(Show, PartialEq, PartialOrd)]
-// pub enum Resp<T> {
-//     /// The route is valid and the functon has filled the response with data
-//     Filled(T),
-//     /// The route is valid but the function don't returned nothing
-//     NoResp,
-//     /// The route is valid but an error has occured inside the user function
-//     InternalError(String),
-//     /// The route is not valid this error is returned by the web dispatcher
-//     RoutingError(String)
-// }
-
-// impl<T> Resp<T> {
-//     pub fn is_success(&self) -> bool {
-//         match *self {
-//             Filled(_)
-//             | NoResp => true,
-//             _        => false
-//         }
-//     }
-
-//     pub fn unwrap(self) -> T {
-//         match self {
-//             Filled(t) => t,
-//             _         => fail!()
-//         }
-//     }
-// }
-
-// impl<T: Default> Default for Resp<T> {
-//     fn default() -> Resp<T> {
-//         Filled(Default::default())
-//     }
-// }
+/// Outcomes of the functions of the routes
+#[deriving(Show, PartialEq, PartialOrd)]
+pub enum Resp<T> {
+    /// The route is valid and the functon has filled the response with data
+    Filled(T),
+    /// The route is valid but the function don't returned nothing
+    NoResp,
+    /// The route is valid but an error has occured inside the user function
+    InternalError(String),
+    /// The route is not valid, the dispatcher handles it as a not found route
+    RoutingError(String)
+}
+
+impl<T> Resp<T> {
+    pub fn is_success(&self) -> bool {
+        match *self {
+            Filled(_)
+            | NoResp => true,
+            _        => false
+        }
+    }
+
+    pub fn unwrap(self) -> T {
+        match self {
+            Filled(t) => t,
+            _         => fail!("called `Resp::unwrap()` on a response which is not `Filled`")
+        }
+    }
+
+    /// The http status code matching the outcome
+    pub fn status(&self) -> u16 {
+        match *self {
+            Filled(_)        => 200,
+            NoResp           => 204,
+            InternalError(_) => 500,
+            RoutingError(_)  => 404
+        }
+    }
+}
+
+impl<T: Default> Default for Resp<T> {
+    fn default() -> Resp<T> {
+        Filled(Default::default())
+    }
+}
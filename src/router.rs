@@ -0,0 +1,456 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Segment tree used to match the routes
+//!
+//! Each route is split on `/`, and every segment becomes a node of the tree:
+//!
+//! * `hello` is a static segment, matched by a plain hash lookup
+//! * `:my_var` is a variable, matching any non-empty segment
+//! * `:my_var<constraint>` is a variable whose value must match the constraint,
+//!   either the name of a registered constraint like `int`, or a regex like
+//!   `[a-z-]+`. A constraint made only of `[0-9a-zA-Z_]` is always a name, an
+//!   unknown name makes the route invalid, a regex matching a word must be
+//!   written e.g `(word)`
+//! * `*` is a wildcard, matching any non-empty segment, the n-th wildcard of
+//!   a route is captured as `*n`, starting from `*0`
+//! * `*my_path` is a catch-all, which must end the route, it matches all the
+//!   remaining path including the slashes, even empty
+//!
+//! Parts of a route can be made optional with parentheses, e.g
+//! `/posts(/:page)`, or `/posts/:page?` for a single variable. A variable can
+//! declare the value used when it is absent, e.g `/posts/:page?=1`. A route
+//! with optional parts is expanded into all its possible forms when added.
+//!
+//! The paths are matched once normalised, the segments are decoded and can
+//! contain any character but `/`.
+//!
+//! A lookup only walks the branches compatible with the path, so its cost
+//! depends on the depth of the route, not on the number of routes.
+//!
+//! When several routes match the same path, the most specific one wins:
+//!
+//! 1. segments are compared from left to right, and at the first one which
+//!    differs a static segment beats a constrained variable, which beats a
+//!    variable, which beats a wildcard, which beats a catch-all
+//! 2. if a route is a prefix of the other, the longer route wins, except when
+//!    the longer route only adds a catch-all, which then has an empty tail
+//! 3. if the routes are still equivalent, the first registered wins
+
+use std::collections::HashMap;
+use std::fmt::{Show, Formatter, FormatError};
+
+use regex::Regex;
+
+use error::{DispatchError, InvalidPattern};
+
+/// A segment of a route
+#[deriving(Clone, Show, PartialEq)]
+pub enum Segment {
+    /// A static segment, e.g `hello` in `/hello/:my_var`
+    Static(String),
+    /// A variable segment, e.g `:my_var` in `/hello/:my_var`, with its
+    /// optional constraint and its optional default value
+    Var(String, Option<Constraint>, Option<String>),
+    /// A wildcard segment `*`
+    Wildcard,
+    /// A catch-all segment, e.g `*path` in `/assets/*path`
+    CatchAll(String)
+}
+
+/// A constraint on the value of a variable segment
+#[deriving(Clone)]
+pub struct Constraint {
+    pattern: String,
+    regex: Regex
+}
+
+impl Constraint {
+    /// Create a constraint from a regex, return None if the regex is invalid
+    pub fn new(pattern: &str) -> Option<Constraint> {
+        match Regex::new(format!("^(?:{})$", pattern).as_slice()) {
+            Ok(regex) => Some(Constraint {
+                pattern: pattern.to_string(),
+                regex: regex
+            }),
+            Err(_) => None
+        }
+    }
+
+    /// Check if a segment satisfies the constraint
+    pub fn is_match(&self, segment: &str) -> bool {
+        self.regex.is_match(segment)
+    }
+}
+
+impl PartialEq for Constraint {
+    fn eq(&self, other: &Constraint) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Show for Constraint {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        write!(f, "<{}>", self.pattern)
+    }
+}
+
+/// The constraints available by default in the routes
+pub fn default_constraints() -> HashMap<String, String> {
+    let mut constraints = HashMap::new();
+    constraints.insert("int".to_string(), "-?[0-9]+".to_string());
+    constraints.insert("uint".to_string(), "[0-9]+".to_string());
+    constraints.insert("alpha".to_string(), "[a-zA-Z]+".to_string());
+    constraints.insert("alnum".to_string(), "[a-zA-Z0-9]+".to_string());
+    constraints.insert("uuid".to_string(),
+                       "[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}"
+                       .to_string());
+    constraints
+}
+
+/// Expand the optional parts of a route into all the possible routes,
+/// the longest first
+pub fn expand_route(route: &str) -> Result<Vec<String>, DispatchError> {
+    match expand_groups(optional_vars_to_groups(route).as_slice()) {
+        Some(routes) => Ok(routes),
+        None         => Err(InvalidPattern(route.to_string()))
+    }
+}
+
+// rewrite the optional variables `/:name?` as optional groups `(/:name)`
+fn optional_vars_to_groups(route: &str) -> String {
+    let mut r = String::new();
+    for (i, s) in route.split('/').enumerate() {
+        if i > 0 && s.starts_with(":") && s.ends_with("?") {
+            r.push_str(format!("(/{})", s.slice_to(s.len() - 1)).as_slice());
+        } else if i > 0 && s.starts_with(":") && s.contains("?=") {
+            r.push_str(format!("(/{})", s.replace("?=", "=")).as_slice());
+        } else {
+            if i > 0 {
+                r.push_char('/');
+            }
+            r.push_str(s);
+        }
+    }
+    r
+}
+
+// expand the optional groups, return None if the parentheses are unbalanced
+fn expand_groups(route: &str) -> Option<Vec<String>> {
+    // parentheses inside a constraint are part of its regex
+    let mut constraint_depth = 0u;
+    let mut depth = 0u;
+    let mut start = None;
+    for (i, c) in route.char_indices() {
+        match c {
+            '<' => constraint_depth += 1,
+            '>' if constraint_depth > 0 => constraint_depth -= 1,
+            _ if constraint_depth > 0 => {},
+            '(' => {
+                if depth == 0 {
+                    start = Some(i);
+                }
+                depth += 1;
+            },
+            ')' if depth == 0 => return None,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let s = start.unwrap();
+                    let with = format!("{}{}{}",
+                                       route.slice_to(s),
+                                       route.slice(s + 1, i),
+                                       route.slice_from(i + 1));
+                    let without = format!("{}{}", route.slice_to(s), route.slice_from(i + 1));
+                    let mut routes = match expand_groups(with.as_slice()) {
+                        Some(r) => r,
+                        None    => return None
+                    };
+                    match expand_groups(without.as_slice()) {
+                        Some(r) => routes.push_all(r.as_slice()),
+                        None    => return None
+                    }
+                    return Some(routes);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    if depth == 0 {
+        Some(vec![route.to_string()])
+    } else {
+        None
+    }
+}
+
+/// Split a route into its segments, the names of the constraints are looked
+/// up in `constraints`, any other constraint is used as a regex
+pub fn parse_route(route: &str,
+                   constraints: &HashMap<String, String>)
+                   -> Result<Vec<Segment>, DispatchError> {
+    let mut segments = Vec::new();
+    let parts = split_path(route);
+    for (i, s) in parts.iter().enumerate() {
+        if s.starts_with(":") {
+            match parse_var(s.slice_from(1), constraints) {
+                Some(v) => segments.push(v),
+                None    => return Err(InvalidPattern(route.to_string()))
+            }
+        } else if *s == "*" {
+            segments.push(Wildcard)
+        } else if s.starts_with("*") {
+            // a catch-all must be the last segment
+            if i != parts.len() - 1 {
+                return Err(InvalidPattern(route.to_string()));
+            }
+            segments.push(CatchAll(s.slice_from(1).to_string()))
+        } else {
+            segments.push(Static(s.to_string()))
+        }
+    }
+    Ok(segments)
+}
+
+// parse a variable segment, without the leading `:`
+fn parse_var(var: &str, constraints: &HashMap<String, String>) -> Option<Segment> {
+    // the default value follows the constraint, if any
+    let constraint_end = match var.rfind('>') {
+        Some(i) => i + 1,
+        None    => 0
+    };
+    let (s, default) = match var.slice_from(constraint_end).find('=') {
+        Some(i) => (var.slice_to(constraint_end + i),
+                    Some(var.slice_from(constraint_end + i + 1).to_string())),
+        None    => (var, None)
+    };
+
+    match s.find('<') {
+        None => Some(Var(s.to_string(), None, default)),
+        Some(i) if s.ends_with(">") && i + 1 < s.len() - 1 => {
+            let c = s.slice(i + 1, s.len() - 1);
+            let pattern = match constraints.find_equiv(&c) {
+                Some(p)            => p.as_slice(),
+                // an unknown name is most likely a typo, not a regex
+                None if is_name(c) => return None,
+                None               => c
+            };
+            match Constraint::new(pattern) {
+                Some(c) => Some(Var(s.slice_to(i).to_string(), Some(c), default)),
+                None    => None
+            }
+        },
+        Some(_) => None
+    }
+}
+
+// check if a constraint is a name, e.g `int`, rather than a regex
+fn is_name(c: &str) -> bool {
+    c.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Split a path on `/`, ignoring the leading and the trailing slash
+pub fn split_path<'a>(path: &'a str) -> Vec<&'a str> {
+    let mut p = path;
+    if p.starts_with("/") { p = p.slice_from(1); }
+    if p.ends_with("/") { p = p.slice_to(p.len() - 1); }
+    if p.is_empty() {
+        Vec::new()
+    } else {
+        p.split('/').collect()
+    }
+}
+
+/// The specificity of a route, the smallest is the most specific
+#[deriving(Clone, Show, PartialEq, Eq)]
+pub struct Specificity {
+    ranks: Vec<uint>
+}
+
+impl Specificity {
+    pub fn new(segments: &[Segment]) -> Specificity {
+        Specificity {
+            ranks: segments.iter().map(|s| match *s {
+                Static(_)          => 0u,
+                Var(_, Some(_), _) => 1u,
+                Var(_, None, _)    => 2u,
+                Wildcard           => 3u,
+                CatchAll(_)        => 4u
+            }).collect()
+        }
+    }
+
+    /// Check if the route contains only static segments
+    pub fn is_static(&self) -> bool {
+        self.ranks.iter().all(|r| *r == 0)
+    }
+}
+
+impl PartialOrd for Specificity {
+    fn partial_cmp(&self, other: &Specificity) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Specificity {
+    fn cmp(&self, other: &Specificity) -> Ordering {
+        for (a, b) in self.ranks.iter().zip(other.ranks.iter()) {
+            match a.cmp(b) {
+                Equal => {},
+                o     => return o
+            }
+        }
+        // a route extended by a catch-all only matches the same path with an
+        // empty tail, the route ending there is the most specific
+        let (len, other_len) = (self.ranks.len(), other.ranks.len());
+        if len == other_len + 1 && self.ranks[len - 1] == 4 {
+            return Greater;
+        }
+        if other_len == len + 1 && other.ranks[other_len - 1] == 4 {
+            return Less;
+        }
+        // the longer route is the most specific
+        other_len.cmp(&len)
+    }
+}
+
+/// A node of the routes tree
+pub struct Node<T> {
+    statics: HashMap<String, Node<T>>,
+    vars: Vec<(Option<Constraint>, Node<T>)>,
+    wildcard: Option<Box<Node<T>>>,
+    catch_all: Option<Box<Node<T>>>,
+    values: Vec<T>
+}
+
+impl<T: PartialEq> Node<T> {
+    pub fn new() -> Node<T> {
+        Node {
+            statics: HashMap::new(),
+            vars: Vec::new(),
+            wildcard: None,
+            catch_all: None,
+            values: Vec::new()
+        }
+    }
+
+    /// Insert a value at the end of the given segments
+    pub fn insert(&mut self, segments: &[Segment], value: T) {
+        if segments.is_empty() {
+            if !self.values.contains(&value) {
+                self.values.push(value);
+            }
+            return;
+        }
+
+        let child = match segments[0] {
+            Static(ref name) => {
+                if !self.statics.contains_key(name) {
+                    self.statics.insert(name.clone(), Node::new());
+                }
+                self.statics.find_mut(name).unwrap()
+            },
+            Var(_, ref constraint, _) => {
+                let pos = match self.vars.iter().position(|&(ref c, _)| c == constraint) {
+                    Some(pos) => pos,
+                    None      => {
+                        self.vars.push((constraint.clone(), Node::new()));
+                        self.vars.len() - 1
+                    }
+                };
+                match *self.vars.get_mut(pos) {
+                    (_, ref mut n) => n
+                }
+            },
+            Wildcard => {
+                if self.wildcard.is_none() {
+                    self.wildcard = Some(box Node::new());
+                }
+                &mut **self.wildcard.as_mut().unwrap()
+            },
+            CatchAll(_) => {
+                if self.catch_all.is_none() {
+                    self.catch_all = Some(box Node::new());
+                }
+                &mut **self.catch_all.as_mut().unwrap()
+            }
+        };
+        child.insert(segments.slice_from(1), value)
+    }
+
+    /// Find all the values matching a path, along with the captured variables.
+    ///
+    /// Static segments are tried first, then the variables, then the wildcards,
+    /// then the catch-all, the values of a same node are returned in their insertion order.
+    pub fn find<'a>(&'a self, path: &[&str]) -> Vec<(&'a T, Vec<String>)> {
+        let mut found = Vec::new();
+        self.collect(path, &mut Vec::new(), &mut found);
+        found
+    }
+
+    fn collect<'a>(&'a self,
+                   path: &[&str],
+                   captures: &mut Vec<String>,
+                   found: &mut Vec<(&'a T, Vec<String>)>) {
+        if path.is_empty() {
+            for v in self.values.iter() {
+                found.push((v, captures.clone()));
+            }
+        } else {
+            let (segment, rest) = (path[0], path.slice_from(1));
+            match self.statics.find_equiv(&segment) {
+                Some(n) => n.collect(rest, captures, found),
+                None    => {}
+            }
+            for &(ref constraint, ref n) in self.vars.iter() {
+                let is_match = !segment.is_empty() && match *constraint {
+                    Some(ref c) => c.is_match(segment),
+                    None        => true
+                };
+                if is_match {
+                    captures.push(segment.to_string());
+                    n.collect(rest, captures, found);
+                    captures.pop();
+                }
+            }
+            match self.wildcard {
+                Some(ref n) if !segment.is_empty() => {
+                    captures.push(segment.to_string());
+                    n.collect(rest, captures, found);
+                    captures.pop();
+                },
+                _ => {}
+            }
+        }
+
+        match self.catch_all {
+            Some(ref n) => {
+                captures.push(path.connect("/"));
+                for v in n.values.iter() {
+                    found.push((v, captures.clone()));
+                }
+                captures.pop();
+            },
+            None => {}
+        }
+    }
+}
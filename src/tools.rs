@@ -22,9 +22,14 @@
 
 //! Some tools types for routes and responses
 
+use std::any::{Any, AnyRefExt};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::task;
 
-use response::{Request, Response};
+use error::DispatchError;
+use request::WebRequest;
+use response::{Request, Response, Resp, Filled, NoResp, InternalError, RoutingError};
 
 /// Function signature for a route
 ///
@@ -39,6 +44,172 @@ use response::{Request, Response};
 #[deriving(Clone)]
 pub type RoutesFnType<U> = fn(r: &Request, u: U) -> Box<Response>;
 
+/// Function signature for a route which can report its failures, e.g by
+/// returning `InternalError`, the failures are returned by the dispatcher as
+/// a `DispatchError`
+pub type RespFnType<U> = fn(r: &Request, u: U) -> Resp<Box<Response>>;
+
+/// A route handler, implemented by the functions of the routes, or by any
+/// struct needing a state, e.g a database pool or some templates
+pub trait Handler<U> {
+    /// Handle a request routed to this handler
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>>;
+}
+
+impl<U> Handler<U> for RoutesFnType<U> {
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>> {
+        Filled((*self)(r, u))
+    }
+}
+
+impl<U> Handler<U> for RespFnType<U> {
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>> {
+        (*self)(r, u)
+    }
+}
+
+/// A boxed closure used as a route handler, the closure owns the values it
+/// captures, e.g a configuration
+///
+///# example
+///
+///```Rust
+/// let greeting = "Hello".to_string();
+/// let f: ClosureHandler<()> = box |&: r: &Request, _: ()| {
+///     println!("{} {}", greeting, r.params().to_string("name"));
+///     box () () as Box<Response>
+/// };
+/// dispatcher.add(f, "/hello/:name", Get);
+///```
+pub type ClosureHandler<U> = Box<Fn(&Request, U) -> Box<Response> + Send + Sync>;
+
+impl<U> Handler<U> for ClosureHandler<U> {
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>> {
+        Filled((**self)(r, u))
+    }
+}
+
+/// A handler made of a state and a function, the state is given to the
+/// function at each call
+///
+///# example
+///
+///```Rust
+/// fn greet(greeting: &String, r: &Request, _: ()) -> Box<Response> {
+///     println!("{} {}", greeting, r.params().to_string("name"));
+///     box () () as Box<Response>
+/// }
+///
+/// dispatcher.add(StateHandler::new("Hello".to_string(), greet), "/hello/:name", Get);
+///```
+pub struct StateHandler<S, U> {
+    state: S,
+    f: fn(state: &S, r: &Request, u: U) -> Box<Response>
+}
+
+impl<S, U> StateHandler<S, U> {
+    pub fn new(state: S, f: fn(&S, &Request, U) -> Box<Response>) -> StateHandler<S, U> {
+        StateHandler {
+            state: state,
+            f: f
+        }
+    }
+}
+
+impl<S, U> Handler<U> for StateHandler<S, U> {
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>> {
+        Filled((self.f)(&self.state, r, u))
+    }
+}
+
+/// A handler run in its own task, so its failure doesn't unwind the task of
+/// the dispatcher: a handler calling `fail!` gives an `InternalError`
+/// outcome with the message of the failure, returned by the dispatcher as a
+/// `HandlerFailed` error.
+///
+/// Only the handler is isolated: the middlewares, the hooks, the producer
+/// and the error handlers still run in the task of the dispatcher, and their
+/// failure unwinds it.
+///
+/// Each request spawns a task, the handler works on a copy of the request,
+/// its params, headers and body included, and its response is converted
+/// with `Response::to_http` before being sent back.
+///
+///# example
+///
+///```Rust
+/// dispatcher.add(Isolated::new(may_fail), "/report/:id", Get);
+///```
+pub struct Isolated<H> {
+    handler: Arc<H>
+}
+
+impl<H> Isolated<H> {
+    pub fn new(handler: H) -> Isolated<H> {
+        Isolated {
+            handler: Arc::new(handler)
+        }
+    }
+}
+
+impl<U: Send, H: Handler<U> + Send + Sync> Handler<U> for Isolated<H> {
+    fn handle(&self, r: &Request, u: U) -> Resp<Box<Response>> {
+        let handler = self.handler.clone();
+        let request = WebRequest::from_request(r);
+        let outcome = task::try(proc() {
+            match handler.handle(&request as &Request, u) {
+                Filled(r)          => Filled(r.to_http()),
+                NoResp             => NoResp,
+                InternalError(msg) => InternalError(msg),
+                RoutingError(msg)  => RoutingError(msg)
+            }
+        });
+        match outcome {
+            Ok(Filled(r))          => Filled(box r as Box<Response>),
+            Ok(NoResp)             => NoResp,
+            Ok(InternalError(msg)) => InternalError(msg),
+            Ok(RoutingError(msg))  => RoutingError(msg),
+            Err(cause)             => InternalError(failure_message(cause))
+        }
+    }
+}
+
+// the message given to `fail!`
+fn failure_message(cause: Box<Any + Send>) -> String {
+    let cause: &Any = &*cause;
+    match cause.as_ref::<&'static str>() {
+        Some(msg) => msg.to_string(),
+        None      => match cause.as_ref::<String>() {
+            Some(msg) => msg.clone(),
+            None      => "Box<Any>".to_string()
+        }
+    }
+}
+
+/// Function signature for an error handler, building the response of a
+/// request which cannot be dispatched. The handler sets the status of the
+/// response, e.g 404 for a `NotFound` error.
+pub type ErrorFnType = fn(r: &Request, e: &DispatchError) -> Box<Response>;
+
+/// An error handler, see `Dispatcher::set_not_found` and
+/// `Dispatcher::set_error_handler`
+pub trait ErrorHandler {
+    /// Build the response of a request failed with `e`
+    fn handle_error(&self, r: &Request, e: &DispatchError) -> Box<Response>;
+}
+
+impl ErrorHandler for ErrorFnType {
+    fn handle_error(&self, r: &Request, e: &DispatchError) -> Box<Response> {
+        (*self)(r, e)
+    }
+}
+
+/// Function signature for a hook, run before the function of a route
+///
+/// * `request` - the request, with the parameters captured in the url
+#[deriving(Clone)]
+pub type HookFnType = fn(request: &mut Request);
+
 /// Retrieve a given type from web params easily
 ///
 ///# example
@@ -61,6 +232,8 @@ pub trait WebParams {
     fn to_f64(&self, &str)    -> Option<f64>;
     fn to_bool(&self, &str)   -> Option<bool>;
     fn to_string(&self, &str) -> Option<String>;
+    /// Retrieve the value captured by the n-th anonymous wildcard `*` of the route
+    fn splat(&self, index: uint) -> Option<String>;
 }
 
 macro_rules! to_type(
@@ -87,6 +260,10 @@ impl WebParams for HashMap<String, String> {
     fn to_f64(&self, param_name: &str)    -> Option<f64>  { to_type!(param_name) }
     fn to_bool(&self, param_name: &str)   -> Option<bool> { to_type!(param_name) }
     fn to_string(&self, param_name: &str) -> Option<String> { to_type!(param_name) }
+
+    fn splat(&self, index: uint) -> Option<String> {
+        self.find(&format!("*{}", index)).map(|s| s.clone())
+    }
 }
 
 /// The trait which should be implemented by structs who can product the user_params
@@ -0,0 +1,147 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Encoding and decoding of the urls
+
+use std::collections::HashMap;
+
+// check if a byte is an unreserved character, see RFC 3986 section 2.3
+fn is_unreserved(b: u8) -> bool {
+    let c = b as char;
+    (c.is_ascii() && c.is_alphanumeric()) || c == '-' || c == '.' || c == '_' || c == '~'
+}
+
+/// Percent-encode a string, only the unreserved characters are kept as is
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        if is_unreserved(b) {
+            encoded.push_char(b as char);
+        } else {
+            encoded.push_str(format!("%{:02X}", b).as_slice());
+        }
+    }
+    encoded
+}
+
+// the value of an hexadecimal digit
+fn hex_value(b: u8) -> Option<u8> {
+    (b as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Percent-decode a string, the invalid escapes are kept as is
+pub fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0u;
+    while i < bytes.len() {
+        if bytes[i] == '%' as u8 && i + 2 < bytes.len() {
+            match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                (Some(h), Some(l)) => {
+                    decoded.push(h * 16 + l);
+                    i += 3;
+                    continue;
+                },
+                _ => {}
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Decode a component of a query string or of a form, `+` is a space
+pub fn decode_form_component(s: &str) -> String {
+    let decoded = percent_decode(s.replace("+", " ").as_slice());
+    String::from_utf8_lossy(decoded.as_slice()).into_string()
+}
+
+/// Parse a query string or an urlencoded form, e.g `a=1&b=2&a=3`, a key can
+/// have several values
+pub fn parse_query(s: &str) -> HashMap<String, Vec<String>> {
+    let mut params: HashMap<String, Vec<String>> = HashMap::new();
+    for pair in s.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.find('=') {
+            Some(i) => (pair.slice_to(i), pair.slice_from(i + 1)),
+            None    => (pair, "")
+        };
+        let key = decode_form_component(key);
+        let value = decode_form_component(value);
+        if !params.contains_key(&key) {
+            params.insert(key.clone(), Vec::new());
+        }
+        params.find_mut(&key).unwrap().push(value);
+    }
+    params
+}
+
+/// Split a request target in its path and its query string, the fragment is
+/// dropped, e.g `/a/b?c=1#d` gives `/a/b` and `c=1`
+pub fn split_target<'a>(target: &'a str) -> (&'a str, Option<&'a str>) {
+    let target = match target.find('#') {
+        Some(i) => target.slice_to(i),
+        None    => target
+    };
+    match target.find('?') {
+        Some(i) => (target.slice_to(i), Some(target.slice_from(i + 1))),
+        None    => (target, None)
+    }
+}
+
+// percent-decode a segment of a path, None if it is not valid utf-8 once decoded
+fn decode_segment(segment: &str) -> Option<String> {
+    String::from_utf8(percent_decode(segment)).ok()
+}
+
+/// Normalise the path of a request: each segment is percent-decoded, the
+/// empty and the `.` segments are removed and a `..` segment removes the
+/// previous one, e.g `/a//b/./c/../caf%C3%A9` gives `/a/b/café`.
+///
+/// An encoded slash `%2F` is kept encoded when `keep_encoded_slashes` is set,
+/// otherwise the path is rejected. A path which is not valid utf-8 once
+/// decoded is rejected too.
+pub fn normalize_path(path: &str, keep_encoded_slashes: bool) -> Option<String> {
+    let mut segments: Vec<String> = Vec::new();
+    for raw in path.split('/') {
+        let pieces: Vec<&str> = raw.split_str("%2F")
+            .flat_map(|p| p.split_str("%2f"))
+            .collect();
+        if pieces.len() > 1 && !keep_encoded_slashes {
+            return None;
+        }
+        let mut decoded = Vec::with_capacity(pieces.len());
+        for piece in pieces.iter() {
+            match decode_segment(*piece) {
+                Some(d) => decoded.push(d),
+                None    => return None
+            }
+        }
+        let segment = decoded.connect("%2F");
+        match segment.as_slice() {
+            "" | "." => {},
+            ".."     => { segments.pop(); },
+            _        => segments.push(segment.clone())
+        }
+    }
+    Some(format!("/{}", segments.connect("/")))
+}
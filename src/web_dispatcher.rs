@@ -30,18 +30,33 @@
 #![experimental]
 #![allow(missing_doc)]
 #![feature(macro_rules)]
-#![feature(default_type_params, phase)]
+#![feature(default_type_params, struct_variant)]
+#![feature(unboxed_closures, unboxed_closure_sugar, overloaded_calls)]
 
-#[phase(plugin, link)]
-extern crate regex_macros;
 extern crate regex;
+extern crate serialize;
 
-pub use dispatcher::Dispatcher;
-pub use tools::{WebParams, RoutesFnType, Producer};
-pub use method::{Method, Get, Post, Head, Delete, Put, Connect};
-pub use response::{Response, Request};
+pub use dispatcher::{Dispatcher, Dispatch, Group};
+pub use dispatcher::{TrailingSlash, IgnoreTrailingSlash, StrictTrailingSlash};
+pub use dispatcher::RedirectTrailingSlash;
+pub use error::{DispatchError, NotFound, MethodNotAllowed, InvalidPattern, HandlerFailed};
+pub use error::{UnknownRoute, MissingParameter, InvalidParameter, InvalidPath};
+pub use tools::{WebParams, RoutesFnType, RespFnType, Handler, StateHandler, HookFnType, Producer};
+pub use tools::{ErrorHandler, ErrorFnType, ClosureHandler, Isolated};
+pub use method::{Method, Get, Post, Head, Delete, Put, Connect, Options, Trace, Patch, Extension};
+pub use response::{Response, Request, HttpResponse};
+pub use request::{Headers, WebRequest};
+pub use body::Part;
+pub use middleware::{Middleware, MiddlewareFnType, Next, RouteInfo};
+pub use response::{Resp, Filled, NoResp, InternalError, RoutingError};
 
 mod tools;
+mod middleware;
+mod error;
 mod response;
+mod request;
+mod body;
 mod method;
+mod router;
+mod url;
 mod dispatcher;
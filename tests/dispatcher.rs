@@ -0,0 +1,1071 @@
+// The MIT License (MIT)
+//
+// Copyright (c) 2014 Jeremy Letang
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Tests of the dispatcher, run with `cargo test`
+
+#![feature(macro_rules, unboxed_closures)]
+
+extern crate web_dispatcher;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task;
+
+use web_dispatcher::{Dispatcher, WebParams, Producer, Handler, StateHandler, ClosureHandler};
+use web_dispatcher::Isolated;
+use web_dispatcher::{StrictTrailingSlash, RedirectTrailingSlash, Next, RouteInfo};
+use web_dispatcher::{Request, Response, HttpResponse, WebRequest};
+use web_dispatcher::{Resp, Filled, NoResp, InternalError, RoutingError};
+use web_dispatcher::{Get, Post, Delete, Head, Options, Patch, Extension};
+use web_dispatcher::{Method, DispatchError, MethodNotAllowed, NotFound, InvalidPattern};
+use web_dispatcher::{HandlerFailed, InvalidPath, UnknownRoute, MissingParameter};
+use web_dispatcher::InvalidParameter;
+
+#[deriving(Default)]
+pub struct StringProducer;
+
+impl Producer<String> for StringProducer {
+    fn get_new(&self) -> String {
+        String::from_str("This is a string from the custom producer")
+    }
+}
+
+// a response telling which handler has been called, in its body and in a
+// header kept by the HEAD requests
+fn handler_response(name: &str) -> Box<Response> {
+    box HttpResponse::ok(name).with_header("X-Handler", name) as Box<Response>
+}
+
+macro_rules! priority_route(
+    ($name:ident) => (
+        pub fn $name(_: &Request, _: String) -> Box<Response> {
+            handler_response(stringify!($name))
+        }
+    )
+)
+
+priority_route!(prio_static)
+priority_route!(prio_var)
+priority_route!(prio_wildcard)
+priority_route!(prio_var_first)
+priority_route!(prio_var_second)
+
+// the name of the handler which answered a request
+fn handler_name(r: Result<Box<Response>, DispatchError>) -> String {
+    r.unwrap().to_http().header("X-Handler").unwrap_or("").to_string()
+}
+
+fn check_priority(dispatcher: &Dispatcher<String, StringProducer>,
+                  route: &str,
+                  expected: &'static str) {
+    assert_eq!(handler_name(dispatcher.run(route, HashMap::new())), expected.to_string());
+}
+
+#[test]
+fn priority_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    // register in the reverse order of precedence
+    dispatcher.add(prio_wildcard, "/prio/*/bar", Get);
+    dispatcher.add(prio_var, "/prio/:my_var/bar", Get);
+    dispatcher.add(prio_static, "/prio/static/bar", Get);
+    dispatcher.add(prio_var_first, "/tie/:first", Get);
+    dispatcher.add(prio_var_second, "/tie/:second", Get);
+
+    for _ in range(0u, 10) {
+        check_priority(&mut dispatcher, "/prio/static/bar", "prio_static");
+        check_priority(&mut dispatcher, "/prio/static/bar/", "prio_static");
+        check_priority(&mut dispatcher, "/prio/other/bar", "prio_var");
+        // the empty segments are collapsed, `/prio//bar` is `/prio/bar`
+        assert!(dispatcher.run("/prio//bar", HashMap::new()).is_err());
+        check_priority(&mut dispatcher, "/prio//other//bar", "prio_var");
+        check_priority(&mut dispatcher, "/tie/value", "prio_var_first");
+    }
+}
+
+#[test]
+fn method_not_allowed_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_var, "/allowed/:id", Post);
+    dispatcher.add(prio_var, "/allowed/:id", Delete);
+
+    match dispatcher.run("/allowed/42", HashMap::new()) {
+        Err(e) => assert_eq!(e, MethodNotAllowed { allowed: vec![Post, Delete, Options] }),
+        Ok(_)  => fail!("GET /allowed/42 should not be dispatched")
+    }
+    match dispatcher.run("/unknown/42", HashMap::new()) {
+        Err(e) => assert_eq!(e, NotFound("/unknown/42".to_string())),
+        Ok(_)  => fail!("GET /unknown/42 should not be dispatched")
+    }
+}
+
+// a request with a custom method
+struct MethodRequest {
+    params: HashMap<String, String>,
+    method: Method
+}
+
+impl Request for MethodRequest {
+    fn params<'r>(&'r self) -> &'r HashMap<String, String> { &self.params }
+    fn add_params(&mut self, params: HashMap<String, String>) {
+        self.params.extend(params.move_iter());
+    }
+    fn method(&self) -> Method { self.method.clone() }
+}
+
+fn run_with_method(dispatcher: &Dispatcher<String, StringProducer>,
+                   route: &str,
+                   method: Method) -> Result<Box<Response>, DispatchError> {
+    let mut request = MethodRequest { params: HashMap::new(), method: method };
+    dispatcher.run_request(route, &mut request as &mut Request)
+}
+
+fn check_method(dispatcher: &Dispatcher<String, StringProducer>,
+                route: &str,
+                method: Method,
+                expected: &'static str) {
+    assert_eq!(handler_name(run_with_method(dispatcher, route, method)), expected.to_string());
+}
+
+#[test]
+fn head_and_options_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_var, "/head/:id", Get);
+    dispatcher.add(prio_static, "/head/explicit", Head);
+    dispatcher.add(prio_static, "/head/explicit", Get);
+
+    check_method(&dispatcher, "/head/42", Head, "prio_var");
+    check_method(&dispatcher, "/head/explicit", Head, "prio_static");
+    let r = run_with_method(&mut dispatcher, "/head/42", Options).unwrap().to_http();
+    assert_eq!(r.header("allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(dispatcher.allowed_methods("/head/42"), vec![Get, Head, Options]);
+
+    dispatcher.set_head_fallback(false);
+    dispatcher.set_auto_options(false);
+    match run_with_method(&mut dispatcher, "/head/42", Head) {
+        Err(e) => assert_eq!(e, MethodNotAllowed { allowed: vec![Get] }),
+        Ok(_)  => fail!("HEAD /head/42 should not be dispatched")
+    }
+    assert!(run_with_method(&mut dispatcher, "/head/42", Options).is_err());
+}
+
+#[test]
+fn extension_method_tests() {
+    for name in ["GET", "PATCH", "TRACE", "OPTIONS", "PROPFIND", "MKCOL"].iter() {
+        let m: Method = from_str(*name).unwrap();
+        assert_eq!(m.to_string(), name.to_string());
+        assert_eq!(from_str::<Method>(m.to_string().as_slice()), Some(m.clone()));
+    }
+    assert_eq!(from_str::<Method>("propfind"), Some(Extension("PROPFIND".to_string())));
+    assert_eq!(Method::extension("mkcol"), Some(Extension("MKCOL".to_string())));
+    assert_eq!(Method::extension("get"), Some(Get));
+    assert_eq!(Method::extension("bad method"), None);
+    // an extension built by hand is compared without case
+    assert_eq!(Extension("propfind".to_string()), Extension("PROPFIND".to_string()));
+    assert_eq!(from_str::<Method>("BAD METHOD"), None);
+
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_var, "/dav/:file", Method::extension("propfind").unwrap());
+    dispatcher.add(prio_static, "/dav/:file", Patch);
+    check_method(&dispatcher, "/dav/notes", from_str("propfind").unwrap(), "prio_var");
+    check_method(&dispatcher, "/dav/notes", Patch, "prio_static");
+    dispatcher.add(prio_wildcard, "/dav/:file", Extension("mkcol".to_string()));
+    check_method(&dispatcher, "/dav/notes", from_str("MKCOL").unwrap(), "prio_wildcard");
+}
+
+#[test]
+fn constraints_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add_constraint("hex", "[0-9a-f]+");
+    dispatcher.add(prio_var_first, "/users/:id<int>", Get);
+    dispatcher.add(prio_var_second, "/users/:name", Get);
+    dispatcher.add(prio_static, "/files/:slug<[a-z-]+>", Get);
+    dispatcher.add(prio_var, "/items/:uuid<uuid>", Get);
+    dispatcher.add(prio_wildcard, "/colors/:c<hex>", Get);
+
+    check_priority(&mut dispatcher, "/users/42", "prio_var_first");
+    check_priority(&mut dispatcher, "/users/abc", "prio_var_second");
+    check_priority(&mut dispatcher, "/files/my-file", "prio_static");
+    check_priority(&mut dispatcher, "/items/0f8fad5b-d9cb-469f-a165-70867728950e", "prio_var");
+    check_priority(&mut dispatcher, "/colors/ff00aa", "prio_wildcard");
+    assert!(dispatcher.run("/files/MyFile", HashMap::new()).is_err());
+    assert!(dispatcher.run("/items/42", HashMap::new()).is_err());
+    assert!(dispatcher.run("/colors/red", HashMap::new()).is_err());
+
+    match dispatcher.try_add(prio_static, "/bad/:id<[a-z>", Get) {
+        Err(e) => assert_eq!(e, InvalidPattern("/bad/:id<[a-z>".to_string())),
+        Ok(_)  => fail!("an invalid constraint should be rejected")
+    }
+    // an unknown constraint name is not used as a regex
+    match dispatcher.try_add(prio_static, "/typo/:id<integr>", Get) {
+        Err(e) => assert_eq!(e, InvalidPattern("/typo/:id<integr>".to_string())),
+        Ok(_)  => fail!("an unknown constraint should be rejected")
+    }
+    assert!(dispatcher.try_add(prio_static, "/shade/:c<(dark)>", Get).is_ok());
+    check_priority(&dispatcher, "/shade/dark", "prio_static");
+    assert!(dispatcher.run("/shade/light", HashMap::new()).is_err());
+}
+
+// dispatch a GET request and return the params filled by the dispatcher
+fn captured_params(dispatcher: &Dispatcher<String, StringProducer>,
+                   route: &str) -> HashMap<String, String> {
+    dispatch_get(dispatcher, route).val0()
+}
+
+// dispatch a GET request, return the params filled by the dispatcher and the
+// name of the handler called
+fn dispatch_get(dispatcher: &Dispatcher<String, StringProducer>,
+                route: &str) -> (HashMap<String, String>, String) {
+    let mut params = HashMap::new();
+    let name = handler_name(dispatcher.run_request(route, &mut params as &mut Request));
+    (params, name)
+}
+
+#[test]
+fn splat_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_static, "/assets/*path", Get);
+    dispatcher.add(prio_var, "/assets/img/*path", Get);
+    dispatcher.add(prio_wildcard, "/proxy/*/to/*/*rest", Get);
+
+    let (p, name) = dispatch_get(&mut dispatcher, "/assets/css/site.css");
+    assert_eq!(p.to_string("path"), Some("css/site.css".to_string()));
+    assert_eq!(name, "prio_static".to_string());
+    let (p, name) = dispatch_get(&mut dispatcher, "/assets/img/icons/logo.png");
+    assert_eq!(p.to_string("path"), Some("icons/logo.png".to_string()));
+    assert_eq!(name, "prio_var".to_string());
+    let p = captured_params(&mut dispatcher, "/assets");
+    assert_eq!(p.to_string("path"), Some("".to_string()));
+    let p = captured_params(&mut dispatcher, "/assets/");
+    assert_eq!(p.to_string("path"), Some("".to_string()));
+
+    let p = captured_params(&mut dispatcher, "/proxy/first/to/second/a/b/c");
+    assert_eq!(p.splat(0), Some("first".to_string()));
+    assert_eq!(p.splat(1), Some("second".to_string()));
+    assert_eq!(p.to_string("rest"), Some("a/b/c".to_string()));
+
+    assert!(dispatcher.try_add(prio_static, "/bad/*path/end", Get).is_err());
+
+    // the route ending where the path ends beats a catch-all with an empty
+    // tail, whatever the registration order and the lookup used
+    dispatcher.add(prio_var_first, "/users/:id/*rest", Get);
+    dispatcher.add(prio_var_second, "/users/:id", Get);
+    dispatcher.add(prio_var_first, "/docs/*rest", Get);
+    dispatcher.add(prio_var_second, "/docs", Get);
+    check_priority(&dispatcher, "/users/5", "prio_var_second");
+    check_priority(&dispatcher, "/users/5/a/b", "prio_var_first");
+    check_priority(&dispatcher, "/docs", "prio_var_second");
+    check_priority(&dispatcher, "/docs/a", "prio_var_first");
+}
+
+#[test]
+fn optional_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_static, "/posts(/:page)", Get);
+    dispatcher.add(prio_var, "/archive/:year<int>(/:month<int>(/:day<int>))", Get);
+    dispatcher.add(prio_wildcard, "/search/:q/:page?=1", Get);
+
+    let p = captured_params(&mut dispatcher, "/posts");
+    assert_eq!(p.to_string("page"), None);
+    let p = captured_params(&mut dispatcher, "/posts/3");
+    assert_eq!(p.to_int("page"), Some(3));
+
+    let p = captured_params(&mut dispatcher, "/archive/2014");
+    assert_eq!(p.to_int("year"), Some(2014));
+    assert_eq!(p.to_int("month"), None);
+    let p = captured_params(&mut dispatcher, "/archive/2014/08/12");
+    assert_eq!(p.to_int("month"), Some(8));
+    assert_eq!(p.to_int("day"), Some(12));
+    assert!(dispatcher.run("/archive/2014/august", HashMap::new()).is_err());
+
+    let p = captured_params(&mut dispatcher, "/search/rust");
+    assert_eq!(p.to_int("page"), Some(1));
+    let p = captured_params(&mut dispatcher, "/search/rust/4");
+    assert_eq!(p.to_int("page"), Some(4));
+
+    assert!(dispatcher.try_add(prio_static, "/bad(/:page", Get).is_err());
+    assert!(dispatcher.try_add(prio_static, "/bad/:page)", Get).is_err());
+}
+
+#[test]
+fn url_for_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add_named("user", prio_var, "/users/:id<int>", Get);
+    dispatcher.add_named("search", prio_var, "/search/:q(/:page<int>)", Get);
+    dispatcher.add_named("asset", prio_static, "/assets/*/files/*path", Get);
+    dispatcher.add(prio_static, "/about", Get);
+    dispatcher.name_route("about", "/about/");
+
+    let mut params = HashMap::new();
+    params.insert("id".to_string(), "42".to_string());
+    assert_eq!(dispatcher.url_for("user", &params), Ok("/users/42".to_string()));
+    assert_eq!(dispatcher.url_for("about", &HashMap::new()), Ok("/about".to_string()));
+
+    params.insert("id".to_string(), "abc".to_string());
+    assert_eq!(dispatcher.url_for("user", &params), Err(InvalidParameter("id".to_string())));
+    assert_eq!(dispatcher.url_for("user", &HashMap::new()),
+               Err(MissingParameter("id".to_string())));
+    assert_eq!(dispatcher.url_for("nope", &HashMap::new()), Err(UnknownRoute("nope".to_string())));
+
+    let mut params = HashMap::new();
+    params.insert("q".to_string(), "rust lang/é".to_string());
+    assert_eq!(dispatcher.url_for("search", &params),
+               Ok("/search/rust%20lang%2F%C3%A9".to_string()));
+    params.insert("page".to_string(), "2".to_string());
+    assert_eq!(dispatcher.url_for("search", &params),
+               Ok("/search/rust%20lang%2F%C3%A9/2".to_string()));
+
+    let mut params = HashMap::new();
+    params.insert("*0".to_string(), "v1".to_string());
+    params.insert("path".to_string(), "css/my site.css".to_string());
+    assert_eq!(dispatcher.url_for("asset", &params),
+               Ok("/assets/v1/files/css/my%20site.css".to_string()));
+}
+
+// hooks adding a param with the list of the hooks run
+macro_rules! hook(
+    ($name:ident) => (
+        pub fn $name(r: &mut Request) {
+            let hooks = match r.params().to_string("hooks") {
+                Some(h) => format!("{}{}", h, stringify!($name)),
+                None    => stringify!($name).to_string()
+            };
+            let mut p = HashMap::new();
+            p.insert("hooks".to_string(), hooks);
+            r.add_params(p);
+        }
+    )
+)
+
+hook!(outer_hook)
+hook!(inner_hook)
+
+#[test]
+fn group_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.group("/api/v1/", |g| {
+        g.add(prio_static, "/users", Get);
+        g.add_named("user", prio_var, "/users/:id<int>", Get);
+        g.group("orders", |g| {
+            g.add(prio_wildcard, "", Get);
+            g.add(prio_var_first, "/:id", Get);
+            g.hook(inner_hook);
+        });
+        g.hook(outer_hook);
+    });
+    dispatcher.add(prio_var_second, "/public", Get);
+
+    let (p, name) = dispatch_get(&mut dispatcher, "/api/v1/users");
+    assert_eq!(name, "prio_static".to_string());
+    assert_eq!(p.to_string("hooks"), Some("outer_hook".to_string()));
+    let (p, name) = dispatch_get(&mut dispatcher, "/api/v1/orders/12");
+    assert_eq!(name, "prio_var_first".to_string());
+    assert_eq!(p.to_string("hooks"), Some("outer_hookinner_hook".to_string()));
+    let (_, name) = dispatch_get(&mut dispatcher, "/api/v1/orders");
+    assert_eq!(name, "prio_wildcard".to_string());
+    let p = captured_params(&mut dispatcher, "/public");
+    assert_eq!(p.to_string("hooks"), None);
+
+    let mut params = HashMap::new();
+    params.insert("id".to_string(), "7".to_string());
+    assert_eq!(dispatcher.url_for("user", &params), Ok("/api/v1/users/7".to_string()));
+    assert!(dispatcher.to_string().as_slice().contains("GET /api/v1/orders/:id"));
+}
+
+pub fn admin_route(_: &Request, _: ()) -> Box<Response> {
+    handler_response("admin_route")
+}
+
+#[test]
+fn mount_tests() {
+    let mut admin = Dispatcher::<()>::new(&[]);
+    admin.add(admin_route, "/users/:id", Get);
+    admin.add(admin_route, "/", Get);
+    admin.add(admin_route, "/login", Post);
+
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_static, "/admin/login", Get);
+    dispatcher.add(prio_static, "/administrator", Get);
+    dispatcher.mount("/admin/", admin);
+
+    let (p, name) = dispatch_get(&mut dispatcher, "/admin/users/3");
+    assert_eq!(name, "admin_route".to_string());
+    assert_eq!(p.to_int("id"), Some(3));
+    let (_, name) = dispatch_get(&mut dispatcher, "/admin");
+    assert_eq!(name, "admin_route".to_string());
+    // not allowed in the mounted dispatcher, found in the parent
+    let (_, name) = dispatch_get(&mut dispatcher, "/admin/login");
+    assert_eq!(name, "prio_static".to_string());
+    check_method(&dispatcher, "/admin/login", Post, "admin_route");
+    match run_with_method(&mut dispatcher, "/admin/login", Delete) {
+        Err(e) => assert_eq!(e, MethodNotAllowed { allowed: vec![Get, Post, Head, Options] }),
+        Ok(_)  => fail!("DELETE /admin/login should not be dispatched")
+    }
+    // the parent answers OPTIONS with the methods of both dispatchers
+    let r = run_with_method(&mut dispatcher, "/admin/login", Options).unwrap().to_http();
+    assert_eq!(r.header("Allow"), Some("GET, POST, HEAD, OPTIONS"));
+    let (_, name) = dispatch_get(&mut dispatcher, "/administrator");
+    assert_eq!(name, "prio_static".to_string());
+    match run_with_method(&mut dispatcher, "/admin/users/3", Post) {
+        Err(e) => assert_eq!(e, MethodNotAllowed { allowed: vec![Get, Head, Options] }),
+        Ok(_)  => fail!("POST /admin/users/3 should not be dispatched")
+    }
+    assert!(dispatcher.run("/admin/unknown", HashMap::new()).is_err());
+}
+
+fn state_route(name: &&'static str, _: &Request, _: String) -> Box<Response> {
+    handler_response(*name)
+}
+
+// a handler implemented by a struct
+struct CountHandler {
+    name: &'static str
+}
+
+impl Handler<String> for CountHandler {
+    fn handle(&self, _: &Request, _: String) -> Resp<Box<Response>> {
+        Filled(handler_response(self.name))
+    }
+}
+
+#[test]
+fn handler_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_static, "/fn", Get);
+    dispatcher.add(StateHandler::new("state_first", state_route), "/state/first", Get);
+    dispatcher.add(StateHandler::new("state_second", state_route), "/state/second", Get);
+    dispatcher.add(CountHandler { name: "struct_handler" }, "/struct/:id", Get);
+    let name = "closure_handler".to_string();
+    let closure: ClosureHandler<String> = box |&: _: &Request, _: String| {
+        handler_response(name.as_slice())
+    };
+    dispatcher.add(closure, "/closure", Get);
+
+    check_priority(&mut dispatcher, "/fn", "prio_static");
+    check_priority(&mut dispatcher, "/state/first", "state_first");
+    check_priority(&mut dispatcher, "/state/second", "state_second");
+    check_priority(&mut dispatcher, "/struct/1", "struct_handler");
+    check_priority(&mut dispatcher, "/closure", "closure_handler");
+}
+
+// a route without side effect, safe to call from several tasks
+pub fn concurrent_route(_: &Request, _: String) -> Box<Response> {
+    box () () as Box<Response>
+}
+
+#[test]
+fn concurrent_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(concurrent_route, "/users/:id<int>", Get);
+    dispatcher.add(concurrent_route, "/assets/*path", Get);
+    let dispatcher = Arc::new(dispatcher);
+
+    let (tx, rx) = channel();
+    for t in range(0u, 8) {
+        let dispatcher = dispatcher.clone();
+        let tx = tx.clone();
+        spawn(proc() {
+            for i in range(0u, 1000) {
+                let id = t * 1000 + i;
+                let p = captured_params(&*dispatcher, format!("/users/{}", id).as_slice());
+                assert_eq!(p.to_uint("id"), Some(id));
+                let p = captured_params(&*dispatcher, format!("/assets/{}/{}", t, i).as_slice());
+                assert_eq!(p.to_string("path"), Some(format!("{}/{}", t, i)));
+            }
+            tx.send(());
+        });
+    }
+    for _ in range(0u, 8) {
+        rx.recv();
+    }
+}
+
+pub fn text_route(p: &Request, _: String) -> Box<Response> {
+    let name = p.params().to_string("name").unwrap();
+    box HttpResponse::ok(format!("hello {}", name).as_slice()) as Box<Response>
+}
+
+pub fn redirect_route(_: &Request, _: String) -> Box<Response> {
+    box HttpResponse::redirect("/hello/world") as Box<Response>
+}
+
+#[test]
+fn http_response_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(text_route, "/hello/:name", Get);
+    dispatcher.add(redirect_route, "/old", Get);
+    dispatcher.add(concurrent_route, "/empty", Get);
+
+    let r = dispatcher.respond("/hello/paul", &mut HashMap::new());
+    assert_eq!(r.status, 200);
+    assert_eq!(r.body, b"hello paul".to_vec());
+    assert_eq!(r.content_type(), Some("text/plain; charset=utf-8"));
+
+    let r = dispatcher.respond("/old", &mut HashMap::new());
+    assert_eq!(r.status, 302);
+    assert_eq!(r.header("Location"), Some("/hello/world"));
+
+    assert_eq!(dispatcher.respond("/empty", &mut HashMap::new()), HttpResponse::new(200));
+    assert_eq!(dispatcher.respond("/nope", &mut HashMap::new()).status, 404);
+
+    let r = run_with_method(&mut dispatcher, "/hello/paul", Post);
+    let r = r.err().unwrap().to_response();
+    assert_eq!(r.status, 405);
+    assert_eq!(r.header("Allow"), Some("GET, HEAD, OPTIONS"));
+
+    let r = run_with_method(&mut dispatcher, "/hello/paul", Head).unwrap().to_http();
+    assert!(r.body.is_empty());
+    assert_eq!(r.header("Content-Length"), Some("10"));
+}
+
+pub fn resp_route(p: &Request, _: String) -> Resp<Box<Response>> {
+    match p.params().to_string("what").unwrap().as_slice() {
+        "filled"  => Filled(box HttpResponse::ok("filled") as Box<Response>),
+        "nothing" => NoResp,
+        "invalid" => RoutingError("invalid".to_string()),
+        _         => InternalError("database is down".to_string())
+    }
+}
+
+#[test]
+fn resp_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(resp_route, "/resp/:what", Get);
+
+    let r = dispatcher.run("/resp/filled", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.body, b"filled".to_vec());
+    let r = dispatcher.run("/resp/nothing", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.status, 204);
+    match dispatcher.run("/resp/invalid", HashMap::new()) {
+        Err(e) => assert_eq!(e, NotFound("/resp/invalid".to_string())),
+        Ok(_)  => fail!("a routing error should be returned as not found")
+    }
+    match dispatcher.run("/resp/error", HashMap::new()) {
+        Err(e) => assert_eq!(e, HandlerFailed("database is down".to_string())),
+        Ok(_)  => fail!("an internal error should be returned as a failure")
+    }
+    assert_eq!(dispatcher.respond("/resp/error", &mut HashMap::new()).status, 500);
+
+    let filled: Resp<()> = Filled(());
+    let nothing: Resp<()> = NoResp;
+    let error: Resp<()> = InternalError("error".to_string());
+    assert_eq!(filled.status(), 200);
+    assert_eq!(nothing.status(), 204);
+    assert!(nothing.is_success());
+    assert!(!error.is_success());
+}
+
+pub fn auth_route(p: &Request, _: String) -> Box<Response> {
+    match p.authorization() {
+        Some("Bearer secret") => box HttpResponse::ok("welcome") as Box<Response>,
+        _                     => box HttpResponse::error(401) as Box<Response>
+    }
+}
+
+#[test]
+fn headers_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(auth_route, "/private", Get);
+
+    let mut request = WebRequest::new(Get, "/private")
+        .with_header("authorization", "Bearer secret")
+        .with_header("Host", "example.com")
+        .with_header("Accept", "text/html;q=0.5, application/json")
+        .with_header("ACCEPT", "text/plain;q=0.8, image/png;q=0")
+        .with_header("X-Custom", "a")
+        .with_header("x-custom", "b")
+        .with_header("Content-Length", " 42");
+    assert_eq!(dispatcher.respond("/private", &mut request).status, 200);
+    assert_eq!(request.header("AUTHORIZATION"), Some("Bearer secret"));
+    assert_eq!(request.header_values("X-CUSTOM"), vec!["a", "b"]);
+    assert_eq!(request.get_host(), "example.com".to_string());
+    assert_eq!(request.content_length(), Some(42));
+    assert_eq!(request.content_type(), None);
+    assert_eq!(request.accept(), vec!["application/json".to_string(),
+                                      "text/plain".to_string(),
+                                      "text/html".to_string()]);
+
+    let mut request = WebRequest::new(Get, "/private");
+    assert_eq!(dispatcher.respond("/private", &mut request).status, 401);
+    // the params map doesn't carry headers
+    assert_eq!(dispatcher.respond("/private", &mut HashMap::new()).status, 401);
+    let params: HashMap<String, String> = HashMap::new();
+    assert_eq!(params.header("Accept"), None);
+}
+
+pub fn form_route(p: &Request, _: String) -> Box<Response> {
+    let form = p.form();
+    let name = form.find_equiv(&"name").map(|v| v[0].clone()).unwrap_or(String::new());
+    // the path param and the form field are kept apart
+    let id = p.params().to_string("id").unwrap();
+    box HttpResponse::ok(format!("{} {}", id, name).as_slice()) as Box<Response>
+}
+
+#[test]
+fn body_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(form_route, "/users/:id", Post);
+
+    let mut request = WebRequest::new(Post, "/users/1")
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(b"name=Paul+Smith&id=2&tag=a&tag=b%26c".to_vec());
+    let r = dispatcher.respond("/users/1", &mut request);
+    assert_eq!(r.body, b"1 Paul Smith".to_vec());
+    let form = request.form();
+    assert_eq!(form.find_equiv(&"tag"), Some(&vec!["a".to_string(), "b&c".to_string()]));
+    assert_eq!(form.find_equiv(&"id"), Some(&vec!["2".to_string()]));
+    assert_eq!(request.params().to_string("id"), Some("1".to_string()));
+
+    let body = b"--XyZ\r\n\
+                 Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+                 Jon\r\n\
+                 --XyZ\r\n\
+                 Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\
+                 Content-Type: image/png\r\n\r\n\
+                 \x89PNG\r\n\
+                 --XyZ--\r\n";
+    let request = WebRequest::new(Post, "/upload")
+        .with_header("Content-Type", "multipart/form-data; boundary=\"XyZ\"")
+        .with_body(body.to_vec());
+    assert_eq!(request.form().find_equiv(&"name"), Some(&vec!["Jon".to_string()]));
+    let parts = request.multipart().unwrap();
+    assert_eq!(parts.len(), 2);
+    assert_eq!(parts[1].filename, Some("me.png".to_string()));
+    assert_eq!(parts[1].headers.get("content-type"), Some("image/png"));
+    assert_eq!(parts[1].data, b"\x89PNG".to_vec());
+
+    let request = WebRequest::new(Post, "/api")
+        .with_header("Content-Type", "application/json; charset=utf-8")
+        .with_body(b"{\"id\": 3}".to_vec());
+    let json = request.json().unwrap();
+    assert_eq!(json.find(&"id".to_string()).and_then(|j| j.as_number()), Some(3.0));
+    let request = WebRequest::new(Post, "/api").with_body(b"{\"id\": 3}".to_vec());
+    assert!(request.json().is_none());
+    assert!(request.form().is_empty());
+}
+
+pub fn namespace_route(p: &Request, _: String) -> Box<Response> {
+    box HttpResponse::ok(p.param("id").unwrap().as_slice()) as Box<Response>
+}
+
+#[test]
+fn namespace_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(namespace_route, "/accounts/:id", Post);
+
+    let mut request = WebRequest::new(Post, "/accounts/1?id=2&id=3&page=4#top")
+        .with_header("Content-Type", "application/x-www-form-urlencoded")
+        .with_body(b"id=5&page=6&name=jon".to_vec());
+    let r = dispatcher.respond("/accounts/1?id=2&id=3&page=4#top", &mut request);
+    // the captured id can't be shadowed by the query or the body
+    assert_eq!(r.body, b"1".to_vec());
+    assert_eq!(request.path_params().find_equiv(&"id"), Some(&"1".to_string()));
+    assert_eq!(request.query_params().find_equiv(&"id"),
+               Some(&vec!["2".to_string(), "3".to_string()]));
+    assert_eq!(request.body_params().find_equiv(&"id"), Some(&vec!["5".to_string()]));
+    assert_eq!(request.param("page"), Some("4".to_string()));
+    assert_eq!(request.param("name"), Some("jon".to_string()));
+    assert_eq!(request.param("unknown"), None);
+
+    let all = request.all_params();
+    assert_eq!(all.to_int("id"), Some(1));
+    assert_eq!(all.to_int("page"), Some(4));
+    assert_eq!(all.to_string("name"), Some("jon".to_string()));
+}
+
+pub fn search_route(p: &Request, _: String) -> Box<Response> {
+    let query = p.query_params();
+    let text = format!("{}|{}|{}",
+                       p.params().to_string("lang").unwrap(),
+                       query.find_equiv(&"q").map(|v| v.connect(",")).unwrap_or(String::new()),
+                       query.find_equiv(&"bad").map(|v| v.connect(",")).unwrap_or(String::new()));
+    box HttpResponse::ok(text.as_slice()) as Box<Response>
+}
+
+#[test]
+fn query_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(search_route, "/search/:lang", Get);
+
+    let body = |target: &str| dispatcher.run(target, HashMap::new()).unwrap().to_http().body;
+    assert_eq!(body("/search/en?q=rust"), b"en|rust|".to_vec());
+    // repeated keys are all kept, in order
+    assert_eq!(body("/search/en?q=rust&q=web&q="), b"en|rust,web,|".to_vec());
+    // `+` is a space and the escapes are decoded
+    assert_eq!(body("/search/fr?q=caf%C3%A9+cr%C3%A8me"), "fr|café crème|".as_bytes().to_vec());
+    // the malformed escapes are kept as is
+    assert_eq!(body("/search/en?bad=%zz%4&bad=100%"), b"en||%zz%4,100%".to_vec());
+    // the fragment is not part of the query
+    assert_eq!(body("/search/en?q=a#q=b"), b"en|a|".to_vec());
+    assert_eq!(body("/search/en?"), b"en||".to_vec());
+
+    // a bare map of params gets the captured params, not the query params
+    let mut params = HashMap::new();
+    dispatcher.run_request("/search/en?q=rust&lang=fr", &mut params as &mut Request).unwrap();
+    assert_eq!(params.to_string("q"), None);
+    assert_eq!(params.to_string("lang"), Some("en".to_string()));
+    // the query of the dispatched target is used by a request whose uri
+    // differs from the target
+    let mut request = WebRequest::new(Get, "/search/en?q=stale");
+    let r = dispatcher.respond("/search/en?q=fresh", &mut request);
+    assert_eq!(r.body, b"en|fresh|".to_vec());
+
+    match dispatcher.run("/nope?q=rust", HashMap::new()) {
+        Err(e) => assert_eq!(e, NotFound("/nope".to_string())),
+        Ok(_)  => fail!("GET /nope should not be dispatched")
+    }
+}
+
+pub fn echo_route(p: &Request, _: String) -> Box<Response> {
+    box HttpResponse::ok(p.params().to_string("name").unwrap().as_slice()) as Box<Response>
+}
+
+#[test]
+fn normalization_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(echo_route, "/users/:name", Get);
+    dispatcher.add(echo_route, "/a/:name", Get);
+
+    let body = |d: &Dispatcher<String, StringProducer>, target: &str| {
+        d.run(target, HashMap::new()).unwrap().to_http().body
+    };
+    // the captured values are decoded
+    assert_eq!(body(&dispatcher, "/users/caf%C3%A9"), "café".as_bytes().to_vec());
+    assert_eq!(body(&dispatcher, "/users/jon%20snow"), b"jon snow".to_vec());
+    assert_eq!(body(&dispatcher, "/users/o'neil+co.(uk)"), b"o'neil+co.(uk)".to_vec());
+    // the urls built by `url_for` route back to their values
+    dispatcher.name_route("user", "/users/:name");
+    let mut params = HashMap::new();
+    params.insert("name".to_string(), "zoë & jon".to_string());
+    let url = dispatcher.url_for("user", &params).unwrap();
+    assert_eq!(body(&dispatcher, url.as_slice()), "zoë & jon".as_bytes().to_vec());
+    // duplicate slashes and dot-segments
+    assert_eq!(body(&dispatcher, "//users///jon"), b"jon".to_vec());
+    assert_eq!(body(&dispatcher, "/a/./b/../c"), b"c".to_vec());
+    assert_eq!(body(&dispatcher, "/../../users/./jon"), b"jon".to_vec());
+    // an encoded dot-segment can't escape the normalisation
+    assert_eq!(body(&dispatcher, "/users/x/y/%2E%2E/%2e"), b"x".to_vec());
+    assert!(dispatcher.run("/users/x/%2E%2E", HashMap::new()).is_err());
+
+    // the encoded slashes and the invalid utf-8 are rejected
+    match dispatcher.run("/users/a%2Fb", HashMap::new()) {
+        Err(e) => assert_eq!(e, InvalidPath("/users/a%2Fb".to_string())),
+        Ok(_)  => fail!("an encoded slash should be rejected")
+    }
+    assert_eq!(dispatcher.respond("/users/%FF", &mut HashMap::new()).status, 400);
+
+    dispatcher.set_keep_encoded_slashes(true);
+    assert_eq!(body(&dispatcher, "/users/a%2fb%20c"), b"a%2Fb c".to_vec());
+
+    // the raw path stays on the request
+    let mut request = WebRequest::new(Get, "/users//caf%C3%A9?x=1");
+    let r = dispatcher.respond("/users//caf%C3%A9", &mut request);
+    assert_eq!(r.body, "café".as_bytes().to_vec());
+    assert_eq!(request.raw_path(), "/users//caf%C3%A9".to_string());
+    // the raw path is the one of the dispatched target, not of the uri
+    let mut request = WebRequest::new(Get, "/elsewhere");
+    assert_eq!(request.raw_path(), "/elsewhere".to_string());
+    dispatcher.respond("/users/a%20b?x=1", &mut request);
+    assert_eq!(request.raw_path(), "/users/a%20b".to_string());
+}
+
+#[test]
+fn trailing_slash_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(prio_static, "/hello/main", Get);
+    dispatcher.add(prio_static, "/hello/main", Post);
+    dispatcher.add(echo_route, "/users/:name/", Get);
+
+    // by default the trailing slash is ignored, by the static and the tree lookups
+    assert!(dispatcher.run("/hello/main/", HashMap::new()).is_ok());
+    assert!(dispatcher.run("/hello/main", HashMap::new()).is_ok());
+    assert!(dispatcher.run("/users/jon/", HashMap::new()).is_ok());
+    assert!(dispatcher.run("/users/jon", HashMap::new()).is_ok());
+
+    dispatcher.set_trailing_slash(StrictTrailingSlash);
+    match dispatcher.run("/hello/main/", HashMap::new()) {
+        Err(e) => assert_eq!(e, NotFound("/hello/main/".to_string())),
+        Ok(_)  => fail!("/hello/main/ should not be found")
+    }
+    assert!(dispatcher.run("/users/jon/", HashMap::new()).is_err());
+    assert!(dispatcher.run("/users/jon", HashMap::new()).is_ok());
+    assert!(dispatcher.run("/", HashMap::new()).is_err());
+
+    dispatcher.set_trailing_slash(RedirectTrailingSlash);
+    let r = dispatcher.run("/hello/main/?a=1", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.status, 301);
+    assert_eq!(r.header("Location"), Some("/hello/main?a=1"));
+    let r = dispatcher.run("/users/caf%C3%A9//", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.header("Location"), Some("/users/caf%C3%A9"));
+    // the method and the body are kept with a 308
+    let mut request = WebRequest::new(Post, "/hello/main/");
+    let r = dispatcher.respond("/hello/main/", &mut request);
+    assert_eq!(r.status, 308);
+    assert_eq!(r.header("Location"), Some("/hello/main"));
+    // the location is the normalised path
+    let r = dispatcher.run("/hello/./main//?a=1", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.header("Location"), Some("/hello/main?a=1"));
+    let r = dispatcher.run("//hello//x/../main/", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.header("Location"), Some("/hello/main"));
+    let r = dispatcher.run("/users/zo%c3%ab%20s/", HashMap::new()).unwrap().to_http();
+    assert_eq!(r.header("Location"), Some("/users/zo%C3%AB%20s"));
+    // no redirection for a method not allowed
+    match run_with_method(&dispatcher, "/hello/main/", Delete) {
+        Err(e) => assert_eq!(e, MethodNotAllowed { allowed: vec![Get, Post, Head, Options] }),
+        Ok(_)  => fail!("DELETE /hello/main/ should not be redirected")
+    }
+    // no redirection to a path which doesn't exist
+    assert_eq!(dispatcher.respond("/nope/", &mut HashMap::new()).status, 404);
+    assert!(dispatcher.run("/hello/main", HashMap::new()).is_ok());
+}
+
+// a middleware adding its name to the `hooks` param before calling next
+macro_rules! chain_middleware(
+    ($name:ident) => (
+        pub fn $name(r: &mut Request, _: &RouteInfo, next: Next<String>) -> Resp<Box<Response>> {
+            let hooks = match r.params().to_string("hooks") {
+                Some(h) => format!("{}{},", h, stringify!($name)),
+                None    => format!("{},", stringify!($name))
+            };
+            let mut p = HashMap::new();
+            p.insert("hooks".to_string(), hooks);
+            r.add_params(p);
+            next.run(r)
+        }
+    )
+)
+
+chain_middleware!(first_middleware)
+chain_middleware!(second_middleware)
+chain_middleware!(route_middleware)
+
+pub fn chain_route(p: &Request, _: String) -> Box<Response> {
+    box HttpResponse::ok(p.params().to_string("hooks").unwrap().as_slice()) as Box<Response>
+}
+
+// reject the requests without a token, without calling the route
+pub fn token_middleware(r: &mut Request, _: &RouteInfo, next: Next<String>) -> Resp<Box<Response>> {
+    match r.authorization() {
+        Some("Bearer secret") => next.run(r),
+        _                     => Filled(box HttpResponse::error(401) as Box<Response>)
+    }
+}
+
+// add the matched route to the response
+pub fn route_header_middleware(r: &mut Request,
+                               info: &RouteInfo,
+                               next: Next<String>) -> Resp<Box<Response>> {
+    let header = format!("{} {} {}", info.method, info.route, info.path);
+    match next.run(r) {
+        Filled(resp) => {
+            let resp = resp.to_http().with_header("X-Route", header.as_slice());
+            Filled(box resp as Box<Response>)
+        },
+        other => other
+    }
+}
+
+#[test]
+fn middleware_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(chain_route, "/chain/:id", Get);
+    dispatcher.add(chain_route, "/other", Get);
+    dispatcher.group("/grouped", |g| {
+        g.hook(outer_hook);
+        g.add(chain_route, "/", Get);
+    });
+    dispatcher.add(auth_route, "/private", Get);
+    dispatcher.add_middleware(route_header_middleware);
+    dispatcher.add_middleware(first_middleware);
+    dispatcher.add_middleware(second_middleware);
+    dispatcher.add_route_middleware("/chain/:id/", Get, route_middleware);
+    dispatcher.add_route_middleware("/private", Get, token_middleware);
+
+    // the middlewares of the dispatcher, then the ones of the route
+    let r = dispatcher.respond("/chain/42", &mut HashMap::new());
+    assert_eq!(r.body, b"first_middleware,second_middleware,route_middleware,".to_vec());
+    assert_eq!(r.header("X-Route"), Some("GET /chain/:id /chain/42"));
+    let r = dispatcher.respond("/other/", &mut HashMap::new());
+    assert_eq!(r.body, b"first_middleware,second_middleware,".to_vec());
+    assert_eq!(r.header("X-Route"), Some("GET /other /other"));
+    // the hooks run after the middlewares
+    let r = dispatcher.respond("/grouped", &mut HashMap::new());
+    assert_eq!(r.body, b"first_middleware,second_middleware,outer_hook".to_vec());
+
+    // a middleware can answer without running the route
+    assert_eq!(dispatcher.respond("/private", &mut HashMap::new()).status, 401);
+    let mut request = WebRequest::new(Get, "/private").with_header("Authorization", "Bearer secret");
+    let r = dispatcher.respond("/private", &mut request);
+    assert_eq!(r.body, b"welcome".to_vec());
+    assert_eq!(r.header("X-Route"), Some("GET /private /private"));
+
+    // the middlewares don't run when no route match
+    assert_eq!(dispatcher.respond("/nope", &mut HashMap::new()).header("X-Route"), None);
+}
+
+pub fn html_not_found(r: &Request, _: &DispatchError) -> Box<Response> {
+    let page = format!("<h1>{} not found</h1>", r.raw_path());
+    box HttpResponse::html(page.as_slice()).with_status(404) as Box<Response>
+}
+
+pub fn html_error(_: &Request, e: &DispatchError) -> Box<Response> {
+    let page = format!("<h1>{}</h1>", e.description());
+    box e.to_response().with_text("text/html", page.as_slice()) as Box<Response>
+}
+
+pub fn json_error(_: &Request, e: &DispatchError) -> Box<Response> {
+    let body = format!("{{\"error\": \"{}\"}}", e.description());
+    box e.to_response().with_text("application/json", body.as_slice()) as Box<Response>
+}
+
+#[test]
+fn error_handler_tests() {
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(text_route, "/hello/:name", Get);
+    dispatcher.group("/api", |g| {
+        g.set_error_handler(json_error);
+        g.add(resp_route, "/resp/:what", Get);
+        g.group("/v2", |g| {
+            g.set_not_found(html_not_found);
+        });
+    });
+
+    // without handlers the errors are converted by `to_response`
+    assert_eq!(dispatcher.respond("/nope", &mut HashMap::new()), HttpResponse::not_found());
+
+    dispatcher.set_not_found(html_not_found);
+    dispatcher.set_error_handler(html_error);
+
+    let r = dispatcher.respond("/nope", &mut WebRequest::new(Get, "/nope"));
+    assert_eq!(r.status, 404);
+    assert_eq!(r.content_type(), Some("text/html; charset=utf-8"));
+    assert_eq!(r.body, b"<h1>/nope not found</h1>".to_vec());
+    let r = dispatcher.respond("/no%20pe?x=1", &mut WebRequest::new(Get, "/stale"));
+    assert_eq!(r.body, b"<h1>/no%20pe not found</h1>".to_vec());
+    let r = dispatcher.respond("/hello/jon", &mut WebRequest::new(Post, "/hello/jon"));
+    assert_eq!(r.status, 405);
+    assert_eq!(r.header("Allow"), Some("GET, HEAD, OPTIONS"));
+    assert_eq!(r.body, b"<h1>method not allowed</h1>".to_vec());
+
+    // the paths of the group use the handlers of the group
+    let r = dispatcher.respond("/api/nope", &mut HashMap::new());
+    assert_eq!(r.status, 404);
+    assert_eq!(r.content_type(), Some("application/json"));
+    assert_eq!(r.body, b"{\"error\": \"route not found\"}".to_vec());
+    let r = dispatcher.respond("/api/resp/error", &mut HashMap::new());
+    assert_eq!(r.status, 500);
+    assert_eq!(r.body, b"{\"error\": \"route handler failed\"}".to_vec());
+    // the innermost group wins, it falls back to the outer group for the
+    // errors it doesn't handle
+    let r = dispatcher.respond("/api/v2/nope", &mut WebRequest::new(Get, "/api/v2/nope"));
+    assert_eq!(r.body, b"<h1>/api/v2/nope not found</h1>".to_vec());
+    let r = dispatcher.respond("/api/v2/../resp/error", &mut HashMap::new());
+    assert_eq!(r.content_type(), Some("application/json"));
+    // `/apis` is not in the `/api` group
+    let r = dispatcher.respond("/apis", &mut HashMap::new());
+    assert_eq!(r.content_type(), Some("text/html; charset=utf-8"));
+
+    // the trailing slash of a group prefix is ignored, the root group
+    // matches all the paths
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(text_route, "/hello/:name", Get);
+    dispatcher.group("/", |g| {
+        g.set_error_handler(html_error);
+    });
+    dispatcher.group("/api/", |g| {
+        g.set_not_found(html_not_found);
+        g.add(resp_route, "/resp/:what", Get);
+    });
+    let r = dispatcher.respond("/api/nope", &mut WebRequest::new(Get, "/api/nope"));
+    assert_eq!(r.body, b"<h1>/api/nope not found</h1>".to_vec());
+    let r = dispatcher.respond("/api", &mut WebRequest::new(Get, "/api"));
+    assert_eq!(r.body, b"<h1>/api not found</h1>".to_vec());
+    let r = dispatcher.respond("/api/resp/error", &mut HashMap::new());
+    assert_eq!(r.body, b"<h1>route handler failed</h1>".to_vec());
+    let r = dispatcher.respond("/hello/jon", &mut WebRequest::new(Post, "/hello/jon"));
+    assert_eq!(r.body, b"<h1>method not allowed</h1>".to_vec());
+    let r = dispatcher.respond("/nope", &mut HashMap::new());
+    assert_eq!(r.body, b"<h1>route not found</h1>".to_vec());
+}
+
+pub fn failing_route(p: &Request, _: String) -> Box<Response> {
+    match p.params().to_string("what").unwrap().as_slice() {
+        "static" => fail!("database is down"),
+        what     => fail!("cannot load {}", what)
+    }
+}
+
+pub fn failing_middleware(_: &mut Request, _: &RouteInfo, _: Next<String>) -> Resp<Box<Response>> {
+    fail!("middleware failed")
+}
+
+fn panic_error(_: &Request, e: &DispatchError) -> Box<Response> {
+    box HttpResponse::error(500).with_text("text/plain", e.to_string().as_slice()) as Box<Response>
+}
+
+#[test]
+fn panic_tests() {
+    // by default a failing route unwinds the task
+    let r = task::try(proc() {
+        let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+        dispatcher.add(failing_route, "/fail/:what", Get);
+        dispatcher.run("/fail/static", HashMap::new()).is_ok()
+    });
+    assert!(r.is_err());
+
+    let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+    dispatcher.add(Isolated::new(failing_route), "/fail/:what", Get);
+    dispatcher.add(Isolated::new(text_route), "/safe/:name", Get);
+    dispatcher.add(text_route, "/hello/:name", Get);
+    dispatcher.set_error_handler(panic_error);
+
+    match dispatcher.run("/fail/static", HashMap::new()) {
+        Err(e) => assert_eq!(e, HandlerFailed("database is down".to_string())),
+        Ok(_)  => fail!("a failing route should give an error")
+    }
+    let r = dispatcher.respond("/fail/user", &mut HashMap::new());
+    assert_eq!(r.status, 500);
+    assert_eq!(r.body, b"route handler failed: cannot load user".to_vec());
+
+    // the dispatcher is still usable
+    let r = dispatcher.respond("/hello/jon", &mut HashMap::new());
+    assert_eq!(r.body, b"hello jon".to_vec());
+    assert!(dispatcher.run("/fail/again", HashMap::new()).is_err());
+    assert!(dispatcher.run("/hello/paul", HashMap::new()).is_ok());
+
+    // an isolated route sees the request and answers as usual
+    let r = dispatcher.respond("/safe/jon", &mut HashMap::new());
+    assert_eq!(r.body, b"hello jon".to_vec());
+    for _ in range(0u, 3) {
+        assert!(dispatcher.run("/fail/static", HashMap::new()).is_err());
+        assert!(dispatcher.run("/safe/paul", HashMap::new()).is_ok());
+    }
+
+    // only the function of the route is isolated, a failing middleware
+    // unwinds the task
+    let r = task::try(proc() {
+        let mut dispatcher = Dispatcher::<String, StringProducer>::new(&[]);
+        dispatcher.add(Isolated::new(text_route), "/safe/:name", Get);
+        dispatcher.add_route_middleware("/safe/:name", Get, failing_middleware);
+        dispatcher.run("/safe/jon", HashMap::new()).is_ok()
+    });
+    assert!(r.is_err());
+}